@@ -1,6 +1,8 @@
 use bevy::prelude::*;
 use some_bevy_tools::{audio_loop::LoopableAudioSource, loading};
 
+use crate::map_asset::MapAsset;
+
 #[derive(Resource, Default, Reflect, Clone)]
 pub struct ImageAssets {
     pub ship: Handle<Image>,
@@ -32,3 +34,18 @@ impl loading::EasyAssetLoader for MusicAssets {
         &[("space", "space.ogg")]
     }
 }
+
+#[derive(Resource, Default, Reflect, Clone)]
+pub struct MapAssets {
+    pub tutorial: Handle<MapAsset>,
+    pub level_1: Handle<MapAsset>,
+}
+impl loading::EasyAssetLoader for MapAssets {
+    type AssetType = MapAsset;
+    fn asset_mapper() -> &'static [(&'static str, &'static str)] {
+        &[
+            ("tutorial", "maps/tutorial.map"),
+            ("level_1", "maps/level_1.map"),
+        ]
+    }
+}
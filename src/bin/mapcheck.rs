@@ -0,0 +1,90 @@
+//! Validates maps without starting the game and prints where their tiles end
+//! up after centering.
+//!
+//! ```text
+//! cargo run --bin mapcheck [map files...]
+//! ```
+//!
+//! Without arguments every `.map` file in `assets/maps` is checked. The exit
+//! code is non-zero if a map can't be loaded or has errors, warnings are only
+//! printed.
+
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use some_bevy_game::error_handler::{GameError, Severity};
+use some_bevy_game::map_asset::{read_map_file, MapAsset};
+
+const MAP_DIRECTORY: &str = "assets/maps";
+
+fn main() -> ExitCode {
+    let mut paths: Vec<PathBuf> = std::env::args().skip(1).map(PathBuf::from).collect();
+    if paths.is_empty() {
+        match map_files(Path::new(MAP_DIRECTORY)) {
+            Ok(map_files) => paths = map_files,
+            Err(err) => {
+                eprintln!("Could not read {}: {}", MAP_DIRECTORY, err);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+
+    let mut failed = false;
+    for path in &paths {
+        println!("== {}", path.display());
+        let map_asset = match read_map_file(path) {
+            Ok(map_asset) => map_asset,
+            Err(err) => {
+                println!("critical: {}", err);
+                failed = true;
+                continue;
+            }
+        };
+        failed |= !check_map(&map_asset);
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn map_files(directory: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut map_files = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "map") {
+            map_files.push(path);
+        }
+    }
+    map_files.sort();
+    Ok(map_files)
+}
+
+/// Prints the problems and the rendered map, returns false on errors.
+fn check_map(map_asset: &MapAsset) -> bool {
+    let mut ok = true;
+    for problem in map_asset.validate() {
+        let problem = GameError::from(problem);
+        match problem.severity() {
+            Severity::Warning => println!("warning: {}", problem),
+            Severity::Error => {
+                println!("error: {}", problem);
+                ok = false;
+            }
+            Severity::Critical => {
+                println!("critical: {}", problem);
+                ok = false;
+            }
+        }
+    }
+    match map_asset.build_map() {
+        Ok(map) => print!("{}", map.render_ascii()),
+        Err(err) => {
+            println!("critical: {}", err);
+            ok = false;
+        }
+    }
+    ok
+}
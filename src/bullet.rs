@@ -10,20 +10,46 @@ use some_bevy_tools::{
 
 use crate::{
     assets::ImageAssets,
+    faction::{Faction, FriendlyFire},
+    respawn,
     ship::{self, Ship},
+    weapon::Weapon,
     StaticWall,
 };
 
-#[derive(PartialEq, Eq, Default)]
+#[derive(PartialEq, Clone, Copy, Debug, Default)]
 pub enum DamagerType {
+    /// Disappears after the first hit.
     #[default]
     SelfDestruct,
+
+    /// Flies through targets and disappears after hitting `max_hits` of them.
+    Piercing { max_hits: u32 },
+
+    /// Damages everything inside of it every `tick_interval` seconds, like
+    /// lava or a radiation cloud.
+    Persistent { tick_interval: f32 },
+
+    /// Explodes on impact and damages everything within `radius`.  The
+    /// damage drops linearly by `falloff` towards the edge, so with a
+    /// falloff of 1.0 there is no damage left at the edge.
+    Explosive { radius: f32, falloff: f32 },
 }
 
 #[derive(Component, Default)]
 pub struct Damager {
     pub damager_type: DamagerType,
     pub strength: f32,
+
+    /// The entity which fired the damager.
+    pub owner: Option<Entity>,
+    pub faction: Option<Faction>,
+
+    /// How many targets the damager has hit so far.
+    pub hits: u32,
+
+    /// Timer for the damage ticks of persistent damagers.
+    pub tick: Timer,
 }
 
 impl Damager {
@@ -31,8 +57,40 @@ impl Damager {
         Self {
             damager_type: DamagerType::SelfDestruct,
             strength,
+            ..default()
+        }
+    }
+
+    pub fn new_piercing(strength: f32, max_hits: u32) -> Self {
+        Self {
+            damager_type: DamagerType::Piercing { max_hits },
+            strength,
+            ..default()
         }
     }
+
+    pub fn new_persistent(strength: f32, tick_interval: f32) -> Self {
+        Self {
+            damager_type: DamagerType::Persistent { tick_interval },
+            strength,
+            tick: Timer::from_seconds(tick_interval, TimerMode::Repeating),
+            ..default()
+        }
+    }
+
+    pub fn new_explosive(strength: f32, radius: f32, falloff: f32) -> Self {
+        Self {
+            damager_type: DamagerType::Explosive { radius, falloff },
+            strength,
+            ..default()
+        }
+    }
+
+    pub fn with_owner(mut self, owner: Entity, faction: Option<Faction>) -> Self {
+        self.owner = Some(owner);
+        self.faction = faction;
+        self
+    }
 }
 
 pub fn damage_system(
@@ -43,17 +101,38 @@ pub fn damage_system(
             Damager,
         >,
     >,
-    damager_query: Query<&Damager>,
-    mut health_query: Query<&mut Health, Without<Damager>>,
+    mut damager_query: Query<&mut Damager>,
+    mut health_query: Query<(&mut Health, Option<&Faction>), Without<Damager>>,
+    friendly_fire: Res<FriendlyFire>,
 ) {
     for CollisionEventStart(health_entity, damager_entity, _) in damage_collisions.read() {
-        if let (Ok(damager), Ok(mut health)) = (
-            damager_query.get(*damager_entity),
+        if let (Ok(mut damager), Ok((mut health, faction))) = (
+            damager_query.get_mut(*damager_entity),
             health_query.get_mut(*health_entity),
         ) {
+            // These have their own systems.
+            if matches!(
+                damager.damager_type,
+                DamagerType::Persistent { .. } | DamagerType::Explosive { .. }
+            ) {
+                continue;
+            }
+            if !friendly_fire.allows(
+                damager.owner,
+                damager.faction,
+                *health_entity,
+                faction.copied(),
+            ) {
+                continue;
+            }
             bevy::log::info!("Damager causes damage: {}", health.get());
             health.modify(-damager.strength);
-            if damager.damager_type == DamagerType::SelfDestruct {
+            damager.hits += 1;
+            let used_up = match damager.damager_type {
+                DamagerType::Piercing { max_hits } => damager.hits >= max_hits,
+                _ => true,
+            };
+            if used_up {
                 commands
                     .entity(*damager_entity)
                     .insert(some_bevy_tools::despawn::AutoDespawn::with_duration(0.1));
@@ -62,6 +141,103 @@ pub fn damage_system(
     }
 }
 
+/// Damages everything inside of persistent damagers on every tick.
+pub fn persistent_damage_system(
+    time: Res<Time>,
+    rapier_context: Res<RapierContext>,
+    mut damager_query: Query<(Entity, &mut Damager)>,
+    mut health_query: Query<(&mut Health, Option<&Faction>), Without<Damager>>,
+    friendly_fire: Res<FriendlyFire>,
+) {
+    for (damager_entity, mut damager) in damager_query.iter_mut() {
+        if !matches!(damager.damager_type, DamagerType::Persistent { .. }) {
+            continue;
+        }
+        if !damager.tick.tick(time.delta()).just_finished() {
+            continue;
+        }
+        for (collider1, collider2, intersecting) in
+            rapier_context.intersection_pairs_with(damager_entity)
+        {
+            let target = if collider1 == damager_entity {
+                collider2
+            } else {
+                collider1
+            };
+            if !intersecting {
+                continue;
+            }
+            if let Ok((mut health, faction)) = health_query.get_mut(target) {
+                if friendly_fire.allows(damager.owner, damager.faction, target, faction.copied()) {
+                    health.modify(-damager.strength);
+                }
+            }
+        }
+    }
+}
+
+/// Lets explosive damagers explode when they hit something with health or
+/// a wall.
+pub fn explosive_damage_system(
+    mut commands: Commands,
+    mut health_collisions: EventReader<CollisionEventStart<Health, Damager>>,
+    mut wall_collisions: EventReader<CollisionEventStart<StaticWall, Damager>>,
+    rapier_context: Res<RapierContext>,
+    mut damager_query: Query<(&mut Damager, &Transform)>,
+    mut health_query: Query<(&Transform, &mut Health, Option<&Faction>), Without<Damager>>,
+    friendly_fire: Res<FriendlyFire>,
+) {
+    let impacts = health_collisions
+        .read()
+        .map(|CollisionEventStart(_, damager, _)| *damager)
+        .chain(
+            wall_collisions
+                .read()
+                .map(|CollisionEventStart(_, damager, _)| *damager),
+        )
+        .collect::<Vec<_>>();
+    for damager_entity in impacts {
+        let Ok((mut damager, transform)) = damager_query.get_mut(damager_entity) else {
+            continue;
+        };
+        let DamagerType::Explosive { radius, falloff } = damager.damager_type else {
+            continue;
+        };
+        // It might hit several things at once but only explodes once.
+        if damager.hits > 0 {
+            continue;
+        }
+        damager.hits = 1;
+
+        let center = transform.translation.xy();
+        let mut targets = Vec::new();
+        rapier_context.intersections_with_shape(
+            center,
+            0.0,
+            &Collider::ball(radius),
+            QueryFilter::default(),
+            |entity| {
+                targets.push(entity);
+                true
+            },
+        );
+        for target in targets {
+            if let Ok((target_transform, mut health, faction)) = health_query.get_mut(target) {
+                if !friendly_fire.allows(damager.owner, damager.faction, target, faction.copied()) {
+                    continue;
+                }
+                let distance = target_transform.translation.xy().distance(center);
+                let factor = (1.0 - falloff * distance / radius).clamp(0.0, 1.0);
+                health.modify(-damager.strength * factor);
+            }
+        }
+        respawn::spawn_explosion(&mut commands, transform.translation, radius);
+        commands
+            .entity(damager_entity)
+            .insert(AutoDespawn::with_frames(0));
+    }
+}
+
 pub struct DamagerPlugin;
 impl Plugin for DamagerPlugin {
     fn build(&self, app: &mut App) {
@@ -71,17 +247,32 @@ impl Plugin for DamagerPlugin {
         if !app.is_plugin_added::<some_bevy_tools::collision_detection::CollisionDetectionPlugin<Health, Damager>>() {
             app.add_plugins(collision_detection::CollisionDetectionPlugin::<Health, Damager>::default());
         }
-        app.add_systems(Update, damage_system);
+        app.init_resource::<FriendlyFire>().add_systems(
+            Update,
+            (
+                damage_system,
+                persistent_damage_system,
+                explosive_damage_system,
+            ),
+        );
     }
 }
 
+/// Despawns everything which died except the player, which respawns instead.
 pub fn despawn_the_dead(
     mut commands: Commands,
     mut death_events: EventReader<some_bevy_tools::health::DeathEvent>,
+    player_query: Query<(), With<ship::Player>>,
 ) {
     for event in death_events.read() {
-        bevy::log::info!("Something died");
-        commands.entity(event.entity).despawn_recursive();
+        if player_query.contains(event.entity) {
+            continue;
+        }
+        // Death events repeat while the health stays at its minimum.
+        if let Some(entity) = commands.get_entity(event.entity) {
+            bevy::log::info!("Something died");
+            entity.despawn_recursive();
+        }
     }
 }
 
@@ -113,6 +304,40 @@ impl BulletBundle {
             auto_despawn: some_bevy_tools::despawn::AutoDespawn::with_duration(5.0),
         }
     }
+
+    pub fn with_owner(mut self, owner: Entity, faction: Option<Faction>) -> Self {
+        self.damage = self.damage.with_owner(owner, faction);
+        self
+    }
+
+    /// Uses the tint and size of the given sprite, the collider matches its size.
+    pub fn with_sprite(mut self, sprite: Sprite) -> Self {
+        let size = sprite.custom_size.unwrap_or(Vec2::new(10.0, 10.0));
+        self.physics_bundle.collider = Collider::cuboid(size.x / 2.0, size.y / 2.0);
+        self.sprite_bundle.sprite = sprite;
+        self
+    }
+
+    /// Replaces the damager but keeps its owner.
+    pub fn with_damager(mut self, damager: Damager) -> Self {
+        self.damage = Damager {
+            owner: self.damage.owner,
+            faction: self.damage.faction,
+            ..damager
+        };
+        self
+    }
+
+    /// Spawns the bullet.  Piercing bullets become sensors, so they fly
+    /// through their targets instead of bouncing off.
+    pub fn spawn(self, commands: &mut Commands) -> Entity {
+        let piercing = matches!(self.damage.damager_type, DamagerType::Piercing { .. });
+        let mut bullet = commands.spawn(self);
+        if piercing {
+            bullet.insert(Sensor);
+        }
+        bullet.id()
+    }
 }
 
 #[derive(Event)]
@@ -120,18 +345,71 @@ pub struct ShootBullet {
     pub ship: Entity,
 }
 
+/// Fires the weapon from 50 pixels in front of `position` towards
+/// `direction` unless it's still reloading.  The projectiles also get the
+/// velocity of the shooter.
+#[allow(clippy::too_many_arguments)]
+pub fn fire_weapon(
+    commands: &mut Commands,
+    image_assets: &ImageAssets,
+    position: Vec2,
+    direction: Vec2,
+    base_velocity: Vec2,
+    weapon: &mut Weapon,
+    owner: Entity,
+    faction: Option<Faction>,
+) {
+    if !weapon.is_ready() {
+        return;
+    }
+    weapon.fire();
+    let position = position + direction * 50.0;
+    for projectile_velocity in weapon.projectile_velocities(direction) {
+        BulletBundle::new(
+            position,
+            image_assets,
+            base_velocity + projectile_velocity,
+            weapon.damage,
+        )
+        .with_sprite(weapon.projectile_look.clone())
+        .with_owner(owner, faction)
+        .spawn(commands);
+    }
+}
+
+/// Fires the weapon of the ship unless it's still reloading.
+#[allow(clippy::type_complexity)]
 pub fn shoot_bullet_system(
     mut commands: Commands,
     mut events: EventReader<ShootBullet>,
     image_assets: Res<ImageAssets>,
-    ship_query: Query<(&Transform, &Velocity, &ship::Direction), With<Ship>>,
+    mut ship_query: Query<
+        (
+            &Transform,
+            &Velocity,
+            &ship::Direction,
+            &mut Weapon,
+            Option<&Faction>,
+        ),
+        With<Ship>,
+    >,
 ) {
     for event in events.read() {
-        if let Ok((transform, velocity, direction)) = ship_query.get(event.ship) {
-            let position = transform.translation.xy() + direction.vector() * 50.0;
-            let velocity = velocity.linvel + direction.vector() * 100.0;
-            commands.spawn(BulletBundle::new(position, &image_assets, velocity, 10.0));
-        }
+        let Ok((transform, velocity, direction, mut weapon, faction)) =
+            ship_query.get_mut(event.ship)
+        else {
+            continue;
+        };
+        fire_weapon(
+            &mut commands,
+            &image_assets,
+            transform.translation.xy(),
+            direction.vector(),
+            velocity.linvel,
+            &mut weapon,
+            event.ship,
+            faction.copied(),
+        );
     }
 }
 
@@ -140,8 +418,17 @@ pub fn despawn_bullet_on_collision<T: Component>(
     mut wall_collider: EventReader<
         some_bevy_tools::collision_detection::CollisionEventStart<T, Damager>,
     >,
+    damager_query: Query<&Damager>,
 ) {
     for CollisionEventStart(_, damager_entity, _) in wall_collider.read() {
+        // Damage zones stay where they are.
+        if let Ok(Damager {
+            damager_type: DamagerType::Persistent { .. },
+            ..
+        }) = damager_query.get(*damager_entity)
+        {
+            continue;
+        }
         commands
             .entity(*damager_entity)
             .insert(AutoDespawn::with_duration(0.1));
@@ -175,3 +462,222 @@ impl Plugin for BulletPlugin {
             );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_builder::{TileMarker, TileType};
+    use crate::test_support::{TestGame, OPEN_SPACE};
+    use some_bevy_tools::controller_2d;
+
+    /// Spawns a single rock and a bullet flying into it.
+    fn shoot_rock(game: &mut TestGame, health: f32) -> uuid::Uuid {
+        let map = game.spawn_map("O", move |c| {
+            (c == 'O').then_some(TileType::Rock(health, Default::default()))
+        });
+        game.with_commands(|commands, image_assets| {
+            commands.spawn(BulletBundle::new(
+                OPEN_SPACE - Vec2::new(0.0, 100.0),
+                image_assets,
+                Vec2::new(0.0, 600.0),
+                10.0,
+            ));
+        });
+        map
+    }
+
+    fn rock_health(game: &mut TestGame, id: uuid::Uuid) -> Option<f32> {
+        game.app
+            .world
+            .query::<(&Health, &TileMarker)>()
+            .iter(&game.app.world)
+            .find(|(_, marker)| marker.0 == id)
+            .map(|(health, _)| health.get())
+    }
+
+    /// Spawns a ship-like target far away from the tutorial.
+    fn spawn_target(game: &mut TestGame, faction: Faction) -> Entity {
+        spawn_target_at(game, OPEN_SPACE, faction)
+    }
+
+    fn spawn_target_at(game: &mut TestGame, position: Vec2, faction: Faction) -> Entity {
+        game.app
+            .world
+            .spawn((
+                PhysicsBundle::fixed_rectangle(50.0, 50.0),
+                Transform::from_translation(position.extend(0.0)),
+                GlobalTransform::default(),
+                Health::new(0.0, 100.0),
+                faction,
+            ))
+            .id()
+    }
+
+    /// Fires a bullet from below into the target and returns its health afterwards.
+    fn hit_target(game: &mut TestGame, target: Entity, owner: Entity, faction: Faction) -> f32 {
+        // Bullets which bounced off earlier would block the new one.
+        let old_bullets = game
+            .app
+            .world
+            .query_filtered::<Entity, With<Damager>>()
+            .iter(&game.app.world)
+            .collect::<Vec<_>>();
+        for bullet in old_bullets {
+            game.app.world.despawn(bullet);
+        }
+        game.with_commands(|commands, image_assets| {
+            commands.spawn(
+                BulletBundle::new(
+                    OPEN_SPACE - Vec2::new(0.0, 100.0),
+                    image_assets,
+                    Vec2::new(0.0, 600.0),
+                    10.0,
+                )
+                .with_owner(owner, Some(faction)),
+            );
+        });
+        game.step(30);
+        game.app.world.get::<Health>(target).unwrap().get()
+    }
+
+    fn health(game: &TestGame, target: Entity) -> f32 {
+        game.app.world.get::<Health>(target).unwrap().get()
+    }
+
+    fn fire_from_below(game: &mut TestGame, damager: Damager) -> Entity {
+        let mut bullet = Entity::PLACEHOLDER;
+        game.with_commands(|commands, image_assets| {
+            bullet = BulletBundle::new(
+                OPEN_SPACE - Vec2::new(0.0, 100.0),
+                image_assets,
+                Vec2::new(0.0, 600.0),
+                10.0,
+            )
+            .with_damager(damager)
+            .spawn(commands);
+        });
+        bullet
+    }
+
+    #[test]
+    fn piercing_bullet_flies_through_targets() {
+        let mut game = TestGame::new();
+        let first = spawn_target(&mut game, Faction::Enemy);
+        let second = spawn_target_at(
+            &mut game,
+            OPEN_SPACE + Vec2::new(0.0, 100.0),
+            Faction::Enemy,
+        );
+        let third = spawn_target_at(
+            &mut game,
+            OPEN_SPACE + Vec2::new(0.0, 200.0),
+            Faction::Enemy,
+        );
+        let bullet = fire_from_below(&mut game, Damager::new_piercing(10.0, 2));
+        game.step(60);
+        assert_eq!(health(&game, first), 90.0);
+        assert_eq!(health(&game, second), 90.0);
+        assert_eq!(health(&game, third), 100.0);
+        assert!(game.app.world.get_entity(bullet).is_none());
+    }
+
+    #[test]
+    fn hazard_tile_damages_on_every_tick() {
+        let mut game = TestGame::new();
+        game.spawn_map("~", |c| (c == '~').then_some(TileType::Hazard(5.0, 0.5)));
+        let target = spawn_target(&mut game, Faction::Player);
+        game.step(5);
+        assert_eq!(health(&game, target), 100.0);
+        // Two ticks pass within a bit more than a second.
+        game.step(62);
+        assert_eq!(health(&game, target), 90.0);
+        assert!(game
+            .app
+            .world
+            .query::<&Damager>()
+            .iter(&game.app.world)
+            .any(|damager| matches!(damager.damager_type, DamagerType::Persistent { .. })));
+    }
+
+    #[test]
+    fn explosion_damages_everything_in_radius() {
+        let mut game = TestGame::new();
+        let hit = spawn_target(&mut game, Faction::Enemy);
+        let near = spawn_target_at(&mut game, OPEN_SPACE + Vec2::new(80.0, 0.0), Faction::Enemy);
+        let far = spawn_target_at(
+            &mut game,
+            OPEN_SPACE + Vec2::new(400.0, 0.0),
+            Faction::Enemy,
+        );
+        fire_from_below(&mut game, Damager::new_explosive(40.0, 150.0, 0.5));
+        game.step(30);
+        let hit_damage = 100.0 - health(&game, hit);
+        let near_damage = 100.0 - health(&game, near);
+        assert!(hit_damage > near_damage, "{} > {}", hit_damage, near_damage);
+        assert!(near_damage > 0.0);
+        assert_eq!(health(&game, far), 100.0);
+    }
+
+    #[test]
+    fn player_bullet_damages_enemy() {
+        let mut game = TestGame::new();
+        let player = game.player();
+        let enemy = spawn_target(&mut game, Faction::Enemy);
+        assert_eq!(hit_target(&mut game, enemy, player, Faction::Player), 90.0);
+    }
+
+    #[test]
+    fn enemy_bullet_hurts_enemy_only_with_friendly_fire() {
+        let mut game = TestGame::new();
+        let shooter = game.app.world.spawn(Faction::Enemy).id();
+        let enemy = spawn_target(&mut game, Faction::Enemy);
+        assert_eq!(hit_target(&mut game, enemy, shooter, Faction::Enemy), 100.0);
+
+        game.app.world.resource_mut::<FriendlyFire>().same_faction = true;
+        assert_eq!(hit_target(&mut game, enemy, shooter, Faction::Enemy), 90.0);
+    }
+
+    #[test]
+    fn bullet_hurts_its_owner_only_with_self_hits() {
+        let mut game = TestGame::new();
+        let target = spawn_target(&mut game, Faction::Player);
+        assert_eq!(
+            hit_target(&mut game, target, target, Faction::Player),
+            100.0
+        );
+
+        game.app.world.resource_mut::<FriendlyFire>().self_hits = true;
+        assert_eq!(hit_target(&mut game, target, target, Faction::Player), 90.0);
+    }
+
+    #[test]
+    fn action_shoots_bullet() {
+        let mut game = TestGame::new();
+        game.send_action(controller_2d::TopDownAction::Action);
+        game.step(2);
+        let bullets = game
+            .app
+            .world
+            .query_filtered::<(), With<Damager>>()
+            .iter(&game.app.world)
+            .count();
+        assert_eq!(bullets, 1);
+    }
+
+    #[test]
+    fn bullet_damages_rock() {
+        let mut game = TestGame::new();
+        let rock = shoot_rock(&mut game, 30.0);
+        assert_eq!(rock_health(&mut game, rock), Some(30.0));
+        game.step(30);
+        assert_eq!(rock_health(&mut game, rock), Some(20.0));
+    }
+
+    #[test]
+    fn destroyed_rock_is_despawned() {
+        let mut game = TestGame::new();
+        let rock = shoot_rock(&mut game, 10.0);
+        game.step(30);
+        assert_eq!(rock_health(&mut game, rock), None);
+    }
+}
@@ -0,0 +1,322 @@
+#![allow(clippy::type_complexity)]
+#![allow(clippy::too_many_arguments)]
+
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use some_bevy_tools::{
+    collision_detection::CollisionEventStart, despawn, despawn::AutoDespawn, health::DeathEvent,
+    trigger,
+};
+use uuid::Uuid;
+
+use crate::{
+    assets::ImageAssets,
+    enemy::{self, EnemyAi},
+    map_builder::TileMarker,
+    menu::PauseState,
+    ship::Player,
+    GameState,
+};
+
+/// A group of enemies which spawns at once.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Wave {
+    pub enemies: u32,
+    pub enemy_health: f32,
+
+    /// Seconds between the start of the encounter or the end of the previous
+    /// wave and the spawn of this wave.
+    pub delay: f32,
+}
+
+/// Starts an encounter once the player flies through it.
+#[derive(Component, Clone, Default)]
+pub struct EncounterTrigger {
+    pub waves: Vec<Wave>,
+}
+
+/// Blocks the way while an encounter of its map is running.
+#[derive(Component, Default)]
+pub struct Door;
+
+/// Where the enemies of the encounters of its map appear.
+#[derive(Component, Default)]
+pub struct EnemySpawnPoint;
+
+/// Marks enemies which belong to the encounter entity.
+#[derive(Component)]
+pub struct EncounterEnemy(pub Entity);
+
+/// A running encounter.
+#[derive(Component)]
+pub struct Encounter {
+    /// Id of the map the encounter belongs to.
+    pub map: Uuid,
+    pub waves: Vec<Wave>,
+    pub next_wave: usize,
+    pub timer: Timer,
+    pub alive: HashSet<Entity>,
+}
+
+#[derive(Event, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EncounterEvent {
+    /// The doors of the map closed.
+    Started(Uuid),
+
+    /// The wave with the index spawned.
+    WaveSpawned(Uuid, usize),
+
+    /// Every enemy of the last wave is dead and the doors are open again.
+    Completed(Uuid),
+}
+
+fn set_doors_locked(
+    commands: &mut Commands,
+    door_query: &mut Query<(Entity, &TileMarker, &mut Visibility), With<Door>>,
+    map: Uuid,
+    locked: bool,
+) {
+    for (door, marker, mut visibility) in door_query.iter_mut() {
+        if marker.0 != map {
+            continue;
+        }
+        if locked {
+            *visibility = Visibility::Inherited;
+            commands.entity(door).remove::<ColliderDisabled>();
+        } else {
+            *visibility = Visibility::Hidden;
+            commands.entity(door).insert(ColliderDisabled);
+        }
+    }
+}
+
+pub fn start_encounter(
+    mut commands: Commands,
+    mut trigger_events: EventReader<CollisionEventStart<Player, EncounterTrigger>>,
+    trigger_query: Query<(&EncounterTrigger, &TileMarker)>,
+    mut door_query: Query<(Entity, &TileMarker, &mut Visibility), With<Door>>,
+    mut encounter_events: EventWriter<EncounterEvent>,
+) {
+    for CollisionEventStart(_, trigger_entity, _) in trigger_events.read() {
+        let Ok((trigger, marker)) = trigger_query.get(*trigger_entity) else {
+            continue;
+        };
+        let map = marker.0;
+        bevy::log::info!("Encounter started");
+        let first_delay = trigger.waves.first().map_or(0.0, |wave| wave.delay);
+        commands.spawn((
+            Encounter {
+                map,
+                waves: trigger.waves.clone(),
+                next_wave: 0,
+                timer: Timer::from_seconds(first_delay, TimerMode::Once),
+                alive: HashSet::new(),
+            },
+            despawn::Cleanup(GameState::InGame),
+            TileMarker(map),
+        ));
+        commands
+            .entity(*trigger_entity)
+            .remove::<EncounterTrigger>()
+            .insert(AutoDespawn::with_frames(0));
+        set_doors_locked(&mut commands, &mut door_query, map, true);
+        encounter_events.send(EncounterEvent::Started(map));
+    }
+}
+
+/// Spawns the waves one after another and opens the doors once the last
+/// wave is dead.
+pub fn run_encounters(
+    mut commands: Commands,
+    time: Res<Time>,
+    image_assets: Res<ImageAssets>,
+    mut death_events: EventReader<DeathEvent>,
+    mut encounter_query: Query<(Entity, &mut Encounter)>,
+    spawn_point_query: Query<(&Transform, &TileMarker), With<EnemySpawnPoint>>,
+    mut door_query: Query<(Entity, &TileMarker, &mut Visibility), With<Door>>,
+    mut encounter_events: EventWriter<EncounterEvent>,
+) {
+    // Death events repeat while the health stays at its minimum, removing a
+    // dead enemy again does nothing.
+    let dead = death_events
+        .read()
+        .map(|event| event.entity)
+        .collect::<HashSet<_>>();
+    for (encounter_entity, mut encounter) in encounter_query.iter_mut() {
+        encounter.alive.retain(|enemy| !dead.contains(enemy));
+        if !encounter.alive.is_empty() {
+            continue;
+        }
+        let Some(wave) = encounter.waves.get(encounter.next_wave).copied() else {
+            bevy::log::info!("Encounter completed");
+            set_doors_locked(&mut commands, &mut door_query, encounter.map, false);
+            encounter_events.send(EncounterEvent::Completed(encounter.map));
+            commands.entity(encounter_entity).despawn_recursive();
+            continue;
+        };
+        if !encounter.timer.tick(time.delta()).finished() {
+            continue;
+        }
+
+        let spawn_points = spawn_point_query
+            .iter()
+            .filter(|(_, marker)| marker.0 == encounter.map)
+            .map(|(transform, _)| transform.translation.xy())
+            .collect::<Vec<_>>();
+        if spawn_points.is_empty() {
+            bevy::log::warn!("Encounter without spawn points");
+        }
+        for (index, position) in spawn_points
+            .iter()
+            .cycle()
+            .take(wave.enemies as usize)
+            .enumerate()
+        {
+            // Enemies sharing a spawn point appear next to each other.
+            let offset = Vec2::new((index / spawn_points.len()) as f32 * 60.0, 0.0);
+            let enemy = enemy::spawn_enemy(
+                &mut commands,
+                &image_assets,
+                *position + offset,
+                wave.enemy_health,
+                0.0,
+            );
+            commands.entity(enemy).insert((
+                // The arena is small enough to always know where the player is.
+                EnemyAi {
+                    sight_range: f32::INFINITY,
+                    ..EnemyAi::patrol_around(*position + offset, 0.0)
+                },
+                EncounterEnemy(encounter_entity),
+                despawn::Cleanup(GameState::InGame),
+                TileMarker(encounter.map),
+            ));
+            encounter.alive.insert(enemy);
+        }
+        encounter_events.send(EncounterEvent::WaveSpawned(
+            encounter.map,
+            encounter.next_wave,
+        ));
+
+        encounter.next_wave += 1;
+        let next_delay = encounter
+            .waves
+            .get(encounter.next_wave)
+            .map_or(0.0, |wave| wave.delay);
+        encounter.timer = Timer::from_seconds(next_delay, TimerMode::Once);
+    }
+}
+
+pub struct EncounterPlugin;
+impl Plugin for EncounterPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(trigger::PhysicsTriggerPlugin::<Player, EncounterTrigger>::default())
+            .add_event::<EncounterEvent>()
+            .add_systems(
+                Update,
+                (start_encounter, run_encounters)
+                    .chain()
+                    .run_if(in_state(GameState::InGame).and_then(in_state(PauseState::Running))),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_builder::TileType;
+    use crate::test_support::{TestGame, OPEN_SPACE};
+    use some_bevy_tools::health::Health;
+
+    #[derive(Resource, Default)]
+    struct RecordedEvents(Vec<EncounterEvent>);
+
+    fn record_events(
+        mut events: EventReader<EncounterEvent>,
+        mut recorded: ResMut<RecordedEvents>,
+    ) {
+        recorded.0.extend(events.read().copied());
+    }
+
+    fn encounter_enemies(game: &mut TestGame) -> Vec<Entity> {
+        game.app
+            .world
+            .query_filtered::<Entity, With<EncounterEnemy>>()
+            .iter(&game.app.world)
+            .collect()
+    }
+
+    fn kill_all(game: &mut TestGame) {
+        for enemy in encounter_enemies(game) {
+            game.app
+                .world
+                .get_mut::<Health>(enemy)
+                .unwrap()
+                .modify(-1000.0);
+        }
+        game.step(2);
+    }
+
+    fn door_locked(game: &mut TestGame) -> bool {
+        let door = game
+            .app
+            .world
+            .query_filtered::<Entity, With<Door>>()
+            .single(&game.app.world);
+        game.app.world.get::<ColliderDisabled>(door).is_none()
+    }
+
+    #[test]
+    fn waves_lock_the_doors_until_all_enemies_are_dead() {
+        let mut game = TestGame::new();
+        game.app
+            .init_resource::<RecordedEvents>()
+            .add_systems(Update, record_events);
+        // The trigger is the center, the spawn point and the door are to
+        // its right.
+        let map_id = game.spawn_map("A...s..D", |c| match c {
+            'A' => Some(TileType::Encounter(2, 2, 0.5)),
+            's' => Some(TileType::EnemySpawn),
+            'D' => Some(TileType::Door),
+            _ => None,
+        });
+        assert!(!door_locked(&mut game));
+
+        game.teleport_player(OPEN_SPACE);
+        game.step(5);
+        assert!(door_locked(&mut game));
+        assert!(encounter_enemies(&mut game).is_empty());
+
+        // A few frames more than the delay of the waves.
+        game.step(35);
+        assert_eq!(encounter_enemies(&mut game).len(), 2);
+        kill_all(&mut game);
+        assert!(encounter_enemies(&mut game).is_empty());
+        assert!(door_locked(&mut game));
+
+        game.step(35);
+        assert_eq!(encounter_enemies(&mut game).len(), 2);
+        kill_all(&mut game);
+        game.step(2);
+        assert!(!door_locked(&mut game));
+
+        assert_eq!(
+            game.app.world.resource::<RecordedEvents>().0,
+            vec![
+                EncounterEvent::Started(map_id),
+                EncounterEvent::WaveSpawned(map_id, 0),
+                EncounterEvent::WaveSpawned(map_id, 1),
+                EncounterEvent::Completed(map_id),
+            ]
+        );
+        let triggers = game
+            .app
+            .world
+            .query::<&EncounterTrigger>()
+            .iter(&game.app.world)
+            .count();
+        assert_eq!(triggers, 0);
+    }
+}
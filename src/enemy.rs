@@ -0,0 +1,297 @@
+#![allow(clippy::type_complexity)]
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use some_bevy_tools::{health::Health, physics2d};
+
+use crate::{
+    assets::ImageAssets,
+    bullet::ShootBullet,
+    faction::Faction,
+    menu::PauseState,
+    respawn::Respawning,
+    ship::{self, Player, Ship, ShipBundle, Thrust},
+    weapon::{Weapon, WeaponKind},
+    GameState,
+};
+
+/// Distance to a waypoint at which it counts as reached.
+const WAYPOINT_DISTANCE: f32 = 25.0;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum AiState {
+    /// Flies along the waypoints.
+    #[default]
+    Patrol,
+
+    /// Flies towards the player.
+    Chase,
+
+    /// Keeps its distance to the player and shoots.
+    Attack,
+
+    /// Flies away from the player.
+    Flee,
+}
+
+#[derive(Component, Clone, Debug)]
+pub struct EnemyAi {
+    pub state: AiState,
+    pub waypoints: Vec<Vec2>,
+    pub next_waypoint: usize,
+
+    /// Starts chasing the player within this distance.
+    pub sight_range: f32,
+
+    /// Stops approaching and shoots within this distance.
+    pub attack_range: f32,
+
+    /// Flees once the health drops below this fraction of the full health.
+    pub flee_health: f32,
+}
+
+impl Default for EnemyAi {
+    fn default() -> Self {
+        Self {
+            state: AiState::Patrol,
+            waypoints: Vec::new(),
+            next_waypoint: 0,
+            sight_range: 400.0,
+            attack_range: 200.0,
+            flee_health: 0.25,
+        }
+    }
+}
+
+impl EnemyAi {
+    /// Patrols a square around `center`.
+    pub fn patrol_around(center: Vec2, radius: f32) -> Self {
+        Self {
+            waypoints: vec![
+                center + Vec2::new(-radius, -radius),
+                center + Vec2::new(radius, -radius),
+                center + Vec2::new(radius, radius),
+                center + Vec2::new(-radius, radius),
+            ],
+            ..default()
+        }
+    }
+
+    /// Picks the state for the distance to the player, if there is one, and
+    /// the fraction of the health which is left.
+    pub fn decide(&self, player_distance: Option<f32>, health_fraction: f32) -> AiState {
+        match player_distance {
+            Some(distance) if distance > self.sight_range => AiState::Patrol,
+            None => AiState::Patrol,
+            Some(_) if health_fraction < self.flee_health => AiState::Flee,
+            Some(distance) if distance <= self.attack_range => AiState::Attack,
+            Some(_) => AiState::Chase,
+        }
+    }
+}
+
+pub fn spawn_enemy(
+    commands: &mut Commands,
+    image_assets: &ImageAssets,
+    position: Vec2,
+    health: f32,
+    patrol_radius: f32,
+) -> Entity {
+    commands
+        .spawn((
+            ShipBundle {
+                sprite_bundle: SpriteBundle {
+                    texture: image_assets.ship.clone(),
+                    transform: Transform::from_translation(position.extend(0.0)),
+                    sprite: Sprite {
+                        color: Color::rgb(1.0, 0.4, 0.4),
+                        custom_size: Some(Vec2::new(50.0, 50.0)),
+                        ..default()
+                    },
+                    ..default()
+                },
+                physics_bundle: physics2d::PhysicsBundle::dynamic_rectangle(50.0, 50.0),
+                acceleration: physics2d::Acceleration::new(600.0, 150.0),
+                direction: ship::Direction::new(Vec2::NEG_Y),
+                thrust: Thrust::default(),
+                health: Health::new(0.0, health),
+                faction: Faction::Enemy,
+                weapon: Weapon {
+                    fire_rate: 1.0,
+                    ..WeaponKind::Blaster.weapon()
+                },
+                ship: Ship,
+            },
+            // Unlike the player, enemies slow down on their own.
+            Damping {
+                linear_damping: 1.0,
+                angular_damping: 0.0,
+            },
+            EnemyAi::patrol_around(position, patrol_radius),
+        ))
+        .id()
+}
+
+pub fn enemy_ai_system(
+    mut enemy_query: Query<
+        (
+            Entity,
+            &mut EnemyAi,
+            &Transform,
+            &Health,
+            &mut Thrust,
+            &mut ship::Direction,
+        ),
+        Without<Player>,
+    >,
+    player_query: Query<&Transform, (With<Player>, Without<Respawning>)>,
+    mut shoot_events: EventWriter<ShootBullet>,
+) {
+    let player_position = player_query
+        .get_single()
+        .ok()
+        .map(|transform| transform.translation.xy());
+    for (enemy, mut ai, transform, health, mut thrust, mut direction) in enemy_query.iter_mut() {
+        let position = transform.translation.xy();
+        let player_distance = player_position.map(|player| player.distance(position));
+        ai.state = ai.decide(player_distance, health.get() / health.get_end());
+
+        match (ai.state, player_position) {
+            (AiState::Chase, Some(player)) => {
+                thrust.0 = (player - position).normalize_or_zero();
+                direction.turn_to(player - position);
+            }
+            (AiState::Attack, Some(player)) => {
+                // Back off when the player comes too close.
+                thrust.0 = if position.distance(player) < ai.attack_range * 0.6 {
+                    (position - player).normalize_or_zero()
+                } else {
+                    Vec2::ZERO
+                };
+                direction.turn_to(player - position);
+                shoot_events.send(ShootBullet { ship: enemy });
+            }
+            (AiState::Flee, Some(player)) => {
+                thrust.0 = (position - player).normalize_or_zero();
+                direction.turn_to(position - player);
+            }
+            _ => {
+                let Some(waypoint) = ai.waypoints.get(ai.next_waypoint).copied() else {
+                    thrust.0 = Vec2::ZERO;
+                    continue;
+                };
+                if waypoint.distance(position) < WAYPOINT_DISTANCE {
+                    ai.next_waypoint = (ai.next_waypoint + 1) % ai.waypoints.len();
+                }
+                thrust.0 = (waypoint - position).normalize_or_zero();
+                direction.turn_to(waypoint - position);
+            }
+        }
+    }
+}
+
+pub struct EnemyPlugin;
+impl Plugin for EnemyPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            enemy_ai_system
+                .run_if(in_state(GameState::InGame).and_then(in_state(PauseState::Running))),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bullet::Damager;
+    use crate::test_support::{TestGame, OPEN_SPACE};
+
+    fn spawn_test_enemy(game: &mut TestGame) -> Entity {
+        let mut enemy = Entity::PLACEHOLDER;
+        game.with_commands(|commands, image_assets| {
+            enemy = spawn_enemy(commands, image_assets, OPEN_SPACE, 30.0, 100.0);
+        });
+        enemy
+    }
+
+    fn position(game: &TestGame, entity: Entity) -> Vec2 {
+        game.app
+            .world
+            .get::<Transform>(entity)
+            .unwrap()
+            .translation
+            .xy()
+    }
+
+    fn state(game: &TestGame, enemy: Entity) -> AiState {
+        game.app.world.get::<EnemyAi>(enemy).unwrap().state
+    }
+
+    #[test]
+    fn decide_by_distance_and_health() {
+        let ai = EnemyAi::default();
+        assert_eq!(ai.decide(None, 1.0), AiState::Patrol);
+        assert_eq!(ai.decide(Some(1000.0), 1.0), AiState::Patrol);
+        assert_eq!(ai.decide(Some(300.0), 1.0), AiState::Chase);
+        assert_eq!(ai.decide(Some(100.0), 1.0), AiState::Attack);
+        assert_eq!(ai.decide(Some(100.0), 0.1), AiState::Flee);
+        assert_eq!(ai.decide(Some(1000.0), 0.1), AiState::Patrol);
+    }
+
+    #[test]
+    fn patrols_without_player_nearby() {
+        let mut game = TestGame::new();
+        let enemy = spawn_test_enemy(&mut game);
+        game.step(30);
+        assert_eq!(state(&game, enemy), AiState::Patrol);
+        let first_waypoint = OPEN_SPACE + Vec2::new(-100.0, -100.0);
+        assert!(
+            position(&game, enemy).distance(first_waypoint) < OPEN_SPACE.distance(first_waypoint)
+        );
+    }
+
+    #[test]
+    fn chases_player_in_sight() {
+        let mut game = TestGame::new();
+        let enemy = spawn_test_enemy(&mut game);
+        let player_position = OPEN_SPACE + Vec2::new(350.0, 0.0);
+        game.teleport_player(player_position);
+        game.step(30);
+        assert_eq!(state(&game, enemy), AiState::Chase);
+        assert!(position(&game, enemy).distance(player_position) < 350.0);
+    }
+
+    #[test]
+    fn shoots_player_in_range() {
+        let mut game = TestGame::new();
+        let enemy = spawn_test_enemy(&mut game);
+        game.teleport_player(OPEN_SPACE + Vec2::new(0.0, -150.0));
+        game.step(5);
+        assert_eq!(state(&game, enemy), AiState::Attack);
+        let enemy_bullets = game
+            .app
+            .world
+            .query::<&Damager>()
+            .iter(&game.app.world)
+            .filter(|damager| damager.owner == Some(enemy))
+            .count();
+        assert_eq!(enemy_bullets, 1);
+    }
+
+    #[test]
+    fn flees_at_low_health() {
+        let mut game = TestGame::new();
+        let enemy = spawn_test_enemy(&mut game);
+        let player_position = OPEN_SPACE + Vec2::new(250.0, 0.0);
+        game.teleport_player(player_position);
+        game.app
+            .world
+            .get_mut::<Health>(enemy)
+            .unwrap()
+            .modify(-25.0);
+        game.step(30);
+        assert_eq!(state(&game, enemy), AiState::Flee);
+        assert!(position(&game, enemy).distance(player_position) > 250.0);
+    }
+}
@@ -7,6 +7,15 @@ use crate::map_builder;
 pub enum GameError {
     #[error("MapDraftError: {0}")]
     MapDraftError(#[from] map_builder::MapDraftError),
+
+    #[error("Map asset is not loaded: {0}")]
+    MapNotLoaded(&'static str),
+
+    #[error("No script for trigger: {0}")]
+    UnknownScript(String),
+
+    #[error("Script step has nothing to act on, missing the {0}")]
+    MissingScriptTarget(&'static str),
 }
 
 pub enum Severity {
@@ -23,7 +32,18 @@ pub enum Severity {
 impl GameError {
     pub fn severity(&self) -> Severity {
         match self {
+            GameError::MapDraftError(
+                map_builder::MapDraftError::UnreachableTrigger { .. }
+                | map_builder::MapDraftError::MissingScript { .. },
+            ) => Severity::Warning,
+            GameError::MapDraftError(
+                map_builder::MapDraftError::UnknownTile { .. }
+                | map_builder::MapDraftError::CenterOutOfBounds { .. },
+            ) => Severity::Error,
             GameError::MapDraftError(_) => Severity::Critical,
+            GameError::MapNotLoaded(_) => Severity::Critical,
+            GameError::UnknownScript(_) => Severity::Warning,
+            GameError::MissingScriptTarget(_) => Severity::Error,
         }
     }
 }
@@ -41,3 +61,17 @@ pub fn error_handler(result: In<Result<(), GameError>>) {
         },
     }
 }
+
+/// Logs warnings and errors, but passes critical errors on to the caller.
+///
+/// Useful if a function finds several problems and only critical ones should
+/// stop it.
+pub fn log_unless_critical(error: impl Into<GameError>) -> Result<(), GameError> {
+    let error = error.into();
+    match error.severity() {
+        Severity::Warning => bevy::log::warn!("Warning: {}", error),
+        Severity::Error => bevy::log::error!("Error: {}", error),
+        Severity::Critical => return Err(error),
+    }
+    Ok(())
+}
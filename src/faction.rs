@@ -0,0 +1,91 @@
+use bevy::prelude::*;
+
+/// The side a ship or a damager fights for.
+#[derive(Component, Clone, Copy, PartialEq, Eq, Debug, Hash)]
+pub enum Faction {
+    Player,
+    Enemy,
+}
+
+/// Decides which hits cause damage.
+///
+/// Entities without a faction, like rocks, can always be damaged.
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct FriendlyFire {
+    /// Damagers hurt other members of their own faction.
+    pub same_faction: bool,
+
+    /// Damagers hurt the entity which fired them.
+    pub self_hits: bool,
+}
+
+impl FriendlyFire {
+    pub fn allows(
+        &self,
+        owner: Option<Entity>,
+        faction: Option<Faction>,
+        target: Entity,
+        target_faction: Option<Faction>,
+    ) -> bool {
+        if owner == Some(target) {
+            return self.self_hits;
+        }
+        match (faction, target_faction) {
+            (Some(faction), Some(target_faction)) if faction == target_faction => self.same_faction,
+            _ => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OWNER: Entity = Entity::from_raw(1);
+    const TARGET: Entity = Entity::from_raw(2);
+
+    #[test]
+    fn default_rules() {
+        let rules = FriendlyFire::default();
+        assert!(rules.allows(
+            Some(OWNER),
+            Some(Faction::Player),
+            TARGET,
+            Some(Faction::Enemy)
+        ));
+        assert!(!rules.allows(
+            Some(OWNER),
+            Some(Faction::Enemy),
+            TARGET,
+            Some(Faction::Enemy)
+        ));
+        assert!(!rules.allows(
+            Some(OWNER),
+            Some(Faction::Player),
+            OWNER,
+            Some(Faction::Player)
+        ));
+        assert!(rules.allows(Some(OWNER), Some(Faction::Player), TARGET, None));
+        assert!(rules.allows(None, None, TARGET, Some(Faction::Player)));
+    }
+
+    #[test]
+    fn enabled_friendly_fire() {
+        let rules = FriendlyFire {
+            same_faction: true,
+            self_hits: true,
+        };
+        assert!(rules.allows(
+            Some(OWNER),
+            Some(Faction::Enemy),
+            TARGET,
+            Some(Faction::Enemy)
+        ));
+        assert!(rules.allows(
+            Some(OWNER),
+            Some(Faction::Player),
+            OWNER,
+            Some(Faction::Player)
+        ));
+    }
+}
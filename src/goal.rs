@@ -0,0 +1,279 @@
+#![allow(clippy::type_complexity)]
+#![allow(clippy::too_many_arguments)]
+
+use std::collections::HashSet;
+
+use bevy::prelude::*;
+use some_bevy_tools::{
+    collision_detection::CollisionEventStart,
+    health::{DeathEvent, HealthMarker},
+    range, trigger,
+};
+use uuid::Uuid;
+
+use crate::{
+    bullet,
+    encounter::{Encounter, EncounterTrigger},
+    faction::Faction,
+    map_builder::{Rock, SpawnedMap, TileMarker},
+    maps::{Level, LevelComplete, LevelExit},
+    menu::PauseState,
+    pickup::{Pickup, PickupKind},
+    replay::ReplayMode,
+    ship::Player,
+    GameState, InGameState,
+};
+
+/// Finishes levels with [`LevelExit::ReachExit`] once the player flies in.
+#[derive(Component, Default)]
+pub struct Exit;
+
+/// Has to be destroyed in levels with [`LevelExit::DestroyObjectives`].
+#[derive(Component, Default)]
+pub struct Objective;
+
+/// What happened in the level the player is in, shown on the results
+/// screen.
+#[derive(Resource, Default)]
+pub struct LevelStats {
+    /// The map the stats belong to, they start over with the next map.
+    pub map: Option<Uuid>,
+    pub level: Level,
+
+    /// Seconds played, not counting pauses.
+    pub time: f32,
+    pub enemies_destroyed: u32,
+    pub rocks_destroyed: u32,
+
+    /// The level was finished, the goal isn't checked anymore.
+    pub completed: bool,
+
+    /// Death events repeat, so every dead entity is only counted once.
+    counted: HashSet<Entity>,
+}
+
+/// What is left of the active map to finish its level.
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct GoalProgress {
+    pub time: f32,
+    pub exit_reached: bool,
+    pub rocks: usize,
+
+    /// Enemies and turrets, plus the encounters which are still to come.
+    pub enemies: usize,
+    pub objectives: usize,
+
+    /// Keys which weren't collected yet, they keep the exit locked.
+    pub keys: usize,
+}
+
+pub fn goal_reached(exit: LevelExit, progress: &GoalProgress) -> bool {
+    match exit {
+        LevelExit::Script => false,
+        LevelExit::ReachExit => progress.exit_reached && progress.keys == 0,
+        LevelExit::DestroyRocks => progress.rocks == 0,
+        LevelExit::DestroyEnemies => progress.enemies == 0,
+        LevelExit::DestroyObjectives => progress.objectives == 0,
+        LevelExit::Survive(seconds) => progress.time >= seconds,
+    }
+}
+
+pub fn track_level_stats(
+    time: Res<Time>,
+    in_game_state: Res<InGameState>,
+    mut stats: ResMut<LevelStats>,
+    mut death_events: EventReader<DeathEvent>,
+    kind_query: Query<(Option<&Rock>, Option<&Faction>), Without<Player>>,
+) {
+    if stats.map != in_game_state.active_map {
+        *stats = LevelStats {
+            map: in_game_state.active_map,
+            level: in_game_state.level,
+            ..default()
+        };
+    }
+    if !stats.completed {
+        stats.time += time.delta_seconds();
+    }
+    for event in death_events.read() {
+        let Ok((rock, faction)) = kind_query.get(event.entity) else {
+            continue;
+        };
+        if !stats.counted.insert(event.entity) {
+            continue;
+        }
+        if rock.is_some() {
+            stats.rocks_destroyed += 1;
+        }
+        if faction == Some(&Faction::Enemy) {
+            stats.enemies_destroyed += 1;
+        }
+    }
+}
+
+/// Finishes the active level once its goal is reached.
+pub fn check_goal(
+    in_game_state: Res<InGameState>,
+    mut stats: ResMut<LevelStats>,
+    mut exit_events: EventReader<CollisionEventStart<Player, Exit>>,
+    map_query: Query<&SpawnedMap>,
+    tile_query: Query<(
+        &TileMarker,
+        Has<Exit>,
+        Has<Rock>,
+        Has<Objective>,
+        Option<&Faction>,
+        Has<EncounterTrigger>,
+        Has<Encounter>,
+        Option<&Pickup>,
+    )>,
+    mut complete_events: EventWriter<LevelComplete>,
+) {
+    let Some(map) = in_game_state.active_map else {
+        return;
+    };
+    let exit_reached = exit_events.read().any(|CollisionEventStart(_, exit, _)| {
+        tile_query
+            .get(*exit)
+            .is_ok_and(|(marker, is_exit, ..)| is_exit && marker.0 == map)
+    });
+    // A freshly spawned map has no tiles yet, which would look like every
+    // rock and enemy is gone.
+    if stats.completed || !map_query.iter().any(|spawned| spawned.id == map) {
+        return;
+    }
+
+    let mut progress = GoalProgress {
+        time: stats.time,
+        exit_reached,
+        ..default()
+    };
+    for (marker, _, rock, objective, faction, trigger, encounter, pickup) in tile_query.iter() {
+        if marker.0 != map {
+            continue;
+        }
+        progress.rocks += rock as usize;
+        progress.objectives += objective as usize;
+        progress.keys += (pickup == Some(&Pickup(PickupKind::Key))) as usize;
+        progress.enemies += (faction == Some(&Faction::Enemy) || trigger || encounter) as usize;
+    }
+    if goal_reached(in_game_state.level.info().exit, &progress) {
+        stats.completed = true;
+        complete_events.send(LevelComplete(in_game_state.level));
+    }
+}
+
+/// Shows the results once a level is finished, however it was finished.
+pub fn finish_level(
+    mut complete_events: EventReader<LevelComplete>,
+    mut stats: ResMut<LevelStats>,
+    replay_mode: Res<ReplayMode>,
+    mut next_pause_state: ResMut<NextState<PauseState>>,
+) {
+    for _ in complete_events.read() {
+        stats.completed = true;
+        // Nobody is there to close the results of a replay.
+        if matches!(*replay_mode, ReplayMode::Off) {
+            next_pause_state.set(PauseState::Results);
+        }
+    }
+}
+
+pub struct GoalPlugin;
+impl Plugin for GoalPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(trigger::PhysicsTriggerPlugin::<Player, Exit>::default())
+            .init_resource::<LevelStats>()
+            .add_systems(
+                Update,
+                (
+                    // The dead are still around to tell what they were.
+                    track_level_stats
+                        .after(range::update_range::<HealthMarker>)
+                        .before(bullet::despawn_the_dead),
+                    check_goal,
+                )
+                    .chain()
+                    .run_if(in_state(GameState::InGame).and_then(in_state(PauseState::Running))),
+            )
+            // Also reads completions sent right before the results opened.
+            .add_systems(Update, finish_level.run_if(in_state(GameState::InGame)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_builder::TileType;
+    use crate::test_support::TestGame;
+    use some_bevy_tools::health::Health;
+
+    #[test]
+    fn goals_depend_on_the_level_exit() {
+        let cleared = GoalProgress::default();
+        let busy = GoalProgress {
+            time: 10.0,
+            exit_reached: false,
+            rocks: 1,
+            enemies: 2,
+            objectives: 3,
+            keys: 0,
+        };
+        for exit in [
+            LevelExit::DestroyRocks,
+            LevelExit::DestroyEnemies,
+            LevelExit::DestroyObjectives,
+        ] {
+            assert!(goal_reached(exit, &cleared));
+            assert!(!goal_reached(exit, &busy));
+        }
+        assert!(!goal_reached(LevelExit::ReachExit, &busy));
+        assert!(goal_reached(
+            LevelExit::ReachExit,
+            &GoalProgress {
+                exit_reached: true,
+                ..busy
+            }
+        ));
+        // Keys left on the map keep the exit locked.
+        assert!(!goal_reached(
+            LevelExit::ReachExit,
+            &GoalProgress {
+                exit_reached: true,
+                keys: 1,
+                ..busy
+            }
+        ));
+        assert!(goal_reached(LevelExit::Survive(10.0), &busy));
+        assert!(!goal_reached(LevelExit::Survive(10.5), &busy));
+        assert!(!goal_reached(LevelExit::Script, &cleared));
+    }
+
+    #[test]
+    fn destroyed_rocks_are_counted_once() {
+        let mut game = TestGame::new();
+        let map_id = game.spawn_map("OO", |c| {
+            (c == 'O').then_some(TileType::Rock(10.0, Default::default()))
+        });
+        let rocks = game
+            .app
+            .world
+            .query_filtered::<(Entity, &TileMarker), With<Rock>>()
+            .iter(&game.app.world)
+            .filter(|(_, marker)| marker.0 == map_id)
+            .map(|(rock, _)| rock)
+            .collect::<Vec<_>>();
+        for rock in rocks {
+            game.app
+                .world
+                .get_mut::<Health>(rock)
+                .unwrap()
+                .modify(-100.0);
+        }
+        game.step(5);
+        let stats = game.app.world.resource::<LevelStats>();
+        assert_eq!(stats.rocks_destroyed, 2);
+        assert_eq!(stats.enemies_destroyed, 0);
+        assert!(!stats.completed);
+    }
+}
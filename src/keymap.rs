@@ -0,0 +1,646 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use bevy::prelude::*;
+use bevy::reflect::{DynamicEnum, DynamicVariant, Enum, TypeInfo, Typed};
+use some_bevy_tools::{
+    controller_2d::TopDownAction,
+    input::{self, ButtonMappingItem, InputMapping, UserButtonInput},
+};
+use thiserror::Error;
+
+use crate::{
+    menu::PauseState,
+    storage::{Storage, StorageError},
+    GameState,
+};
+
+/// Name of the file, or the `localStorage` entry in the browser, the keymap
+/// is stored in.
+pub const KEYMAP_FILE: &str = "keymap.cfg";
+
+#[derive(Error, Debug)]
+pub enum KeymapError {
+    #[error("line {0}: expected `<action> = <key|gamepad> <name>`")]
+    InvalidLine(usize),
+
+    #[error("line {line}: unknown action `{action}`")]
+    UnknownAction { line: usize, action: String },
+
+    #[error("line {line}: invalid binding `{binding}`: {source}")]
+    UnknownBinding {
+        line: usize,
+        binding: String,
+        source: BindingError,
+    },
+
+    #[error("{binding} is already used for {}", action.label())]
+    Conflict {
+        binding: Binding,
+        action: BindableAction,
+    },
+
+    #[error("could not store the keymap: {0}")]
+    Storage(#[from] StorageError),
+}
+
+/// The actions the player can bind to keys and gamepad buttons.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum BindableAction {
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    Fire,
+}
+
+impl BindableAction {
+    pub const ALL: [BindableAction; 5] = [
+        BindableAction::MoveUp,
+        BindableAction::MoveDown,
+        BindableAction::MoveLeft,
+        BindableAction::MoveRight,
+        BindableAction::Fire,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BindableAction::MoveUp => "Move up",
+            BindableAction::MoveDown => "Move down",
+            BindableAction::MoveLeft => "Move left",
+            BindableAction::MoveRight => "Move right",
+            BindableAction::Fire => "Fire",
+        }
+    }
+
+    pub fn top_down_action(&self) -> TopDownAction {
+        match self {
+            BindableAction::MoveUp => TopDownAction::MoveUp,
+            BindableAction::MoveDown => TopDownAction::MoveDown,
+            BindableAction::MoveLeft => TopDownAction::MoveLeft,
+            BindableAction::MoveRight => TopDownAction::MoveRight,
+            BindableAction::Fire => TopDownAction::Action,
+        }
+    }
+}
+
+#[derive(Error, Clone, PartialEq, Debug)]
+#[error("unknown action `{0}`")]
+pub struct UnknownBindableAction(pub String);
+
+impl FromStr for BindableAction {
+    type Err = UnknownBindableAction;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "move_up" => Ok(BindableAction::MoveUp),
+            "move_down" => Ok(BindableAction::MoveDown),
+            "move_left" => Ok(BindableAction::MoveLeft),
+            "move_right" => Ok(BindableAction::MoveRight),
+            "fire" => Ok(BindableAction::Fire),
+            _ => Err(UnknownBindableAction(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for BindableAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BindableAction::MoveUp => write!(f, "move_up"),
+            BindableAction::MoveDown => write!(f, "move_down"),
+            BindableAction::MoveLeft => write!(f, "move_left"),
+            BindableAction::MoveRight => write!(f, "move_right"),
+            BindableAction::Fire => write!(f, "fire"),
+        }
+    }
+}
+
+#[derive(Error, Clone, PartialEq, Debug)]
+pub enum BindingError {
+    #[error("expected `key <name>` or `gamepad <name>`")]
+    InvalidFormat,
+
+    #[error("unknown key `{0}`")]
+    UnknownKey(String),
+
+    #[error("unknown gamepad button `{0}`")]
+    UnknownButton(String),
+}
+
+/// A key or a gamepad button, written as `key KeyW` or `gamepad South`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Binding {
+    Key(KeyCode),
+    Gamepad(GamepadButtonType),
+}
+
+impl Binding {
+    fn is_key(&self) -> bool {
+        matches!(self, Binding::Key(_))
+    }
+}
+
+/// Reads a variant without fields by its name, like `KeyW` for `KeyCode::KeyW`.
+fn unit_variant<T: FromReflect + Typed>(name: &str) -> Option<T> {
+    // Reflection panics on names which aren't variants of the enum.
+    match T::type_info() {
+        TypeInfo::Enum(info) if info.contains_variant(name) => {
+            T::from_reflect(&DynamicEnum::new(name, DynamicVariant::Unit))
+        }
+        _ => None,
+    }
+}
+
+impl FromStr for Binding {
+    type Err = BindingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["key", name] => unit_variant(name)
+                .map(Binding::Key)
+                .ok_or_else(|| BindingError::UnknownKey(name.to_string())),
+            ["gamepad", name] => unit_variant(name)
+                .map(Binding::Gamepad)
+                .ok_or_else(|| BindingError::UnknownButton(name.to_string())),
+            _ => Err(BindingError::InvalidFormat),
+        }
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Binding::Key(key) => write!(f, "key {}", key.variant_name()),
+            Binding::Gamepad(button) => write!(f, "gamepad {}", button.variant_name()),
+        }
+    }
+}
+
+/// The keys and gamepad buttons of every action.
+///
+/// It's stored as one `<action> = <binding>` line per binding, like
+///
+/// ```text
+/// move_up = key KeyW
+/// move_up = gamepad DPadUp
+/// fire = key Space
+/// ```
+#[derive(Resource, Clone, PartialEq, Debug)]
+pub struct Keymap {
+    bindings: BTreeMap<BindableAction, Vec<Binding>>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        use BindableAction::*;
+        use Binding::*;
+        Self {
+            bindings: BTreeMap::from([
+                (
+                    MoveUp,
+                    vec![
+                        Key(KeyCode::KeyW),
+                        Key(KeyCode::ArrowUp),
+                        Gamepad(GamepadButtonType::DPadUp),
+                    ],
+                ),
+                (
+                    MoveDown,
+                    vec![
+                        Key(KeyCode::KeyS),
+                        Key(KeyCode::ArrowDown),
+                        Gamepad(GamepadButtonType::DPadDown),
+                    ],
+                ),
+                (
+                    MoveLeft,
+                    vec![
+                        Key(KeyCode::KeyA),
+                        Key(KeyCode::ArrowLeft),
+                        Gamepad(GamepadButtonType::DPadLeft),
+                    ],
+                ),
+                (
+                    MoveRight,
+                    vec![
+                        Key(KeyCode::KeyD),
+                        Key(KeyCode::ArrowRight),
+                        Gamepad(GamepadButtonType::DPadRight),
+                    ],
+                ),
+                (
+                    Fire,
+                    vec![Key(KeyCode::Space), Gamepad(GamepadButtonType::South)],
+                ),
+            ]),
+        }
+    }
+}
+
+impl Keymap {
+    pub fn bindings(&self, action: BindableAction) -> &[Binding] {
+        self.bindings
+            .get(&action)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    pub fn action_for(&self, binding: Binding) -> Option<BindableAction> {
+        self.bindings
+            .iter()
+            .find(|(_, bindings)| bindings.contains(&binding))
+            .map(|(action, _)| *action)
+    }
+
+    /// Refuses bindings which are used by other actions.
+    fn check_conflict(&self, action: BindableAction, binding: Binding) -> Result<(), KeymapError> {
+        match self.action_for(binding) {
+            Some(other) if other != action => Err(KeymapError::Conflict {
+                binding,
+                action: other,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Replaces the keys of the action if `binding` is a key and its gamepad
+    /// buttons otherwise.  Bindings used by other actions are refused.
+    pub fn rebind(&mut self, action: BindableAction, binding: Binding) -> Result<(), KeymapError> {
+        self.check_conflict(action, binding)?;
+        let bindings = self.bindings.entry(action).or_default();
+        bindings.retain(|other| other.is_key() != binding.is_key());
+        bindings.insert(0, binding);
+        Ok(())
+    }
+
+    /// The bindings of the action for the controls screen.
+    pub fn describe(&self, action: BindableAction) -> String {
+        let names = self
+            .bindings(action)
+            .iter()
+            .map(|binding| match binding {
+                Binding::Key(key) => key.variant_name().to_string(),
+                Binding::Gamepad(button) => format!("Pad {}", button.variant_name()),
+            })
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            "-".to_string()
+        } else {
+            names.join(", ")
+        }
+    }
+
+    /// Parses a stored keymap.  Actions without a line keep their defaults,
+    /// bindings used by two actions are refused like in [`Keymap::rebind`].
+    pub fn parse(source: &str) -> Result<Self, KeymapError> {
+        let mut bindings = BTreeMap::<BindableAction, Vec<Binding>>::new();
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (action, binding) = line
+                .split_once('=')
+                .ok_or(KeymapError::InvalidLine(line_number))?;
+            let (action, binding) = (action.trim(), binding.trim());
+            let action =
+                action
+                    .parse::<BindableAction>()
+                    .map_err(|_| KeymapError::UnknownAction {
+                        line: line_number,
+                        action: action.to_string(),
+                    })?;
+            let binding =
+                binding
+                    .parse::<Binding>()
+                    .map_err(|source| KeymapError::UnknownBinding {
+                        line: line_number,
+                        binding: binding.to_string(),
+                        source,
+                    })?;
+            bindings.entry(action).or_default().push(binding);
+        }
+        let mut keymap = Keymap::default();
+        keymap.bindings.extend(bindings);
+        for (action, bindings) in &keymap.bindings {
+            for binding in bindings {
+                keymap.check_conflict(*action, *binding)?;
+            }
+        }
+        Ok(keymap)
+    }
+}
+
+impl fmt::Display for Keymap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (action, bindings) in &self.bindings {
+            for binding in bindings {
+                writeln!(f, "{} = {}", action, binding)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where the keymap is kept between runs.
+#[derive(Resource)]
+pub struct KeymapStorage(pub Storage);
+
+impl Default for KeymapStorage {
+    fn default() -> Self {
+        Self(Storage::platform_default(KEYMAP_FILE))
+    }
+}
+
+impl KeymapStorage {
+    pub fn read(&self) -> Result<Option<Keymap>, KeymapError> {
+        self.0
+            .read()?
+            .map(|source| Keymap::parse(&source))
+            .transpose()
+    }
+
+    pub fn write(&mut self, keymap: &Keymap) -> Result<(), KeymapError> {
+        let source = format!("# Controls, written by the game.\n{}", keymap);
+        Ok(self.0.write(&source)?)
+    }
+}
+
+/// Falls back to the default keymap if the stored one can't be read.
+pub fn load_keymap(mut keymap: ResMut<Keymap>, storage: Res<KeymapStorage>) {
+    match storage.read() {
+        Ok(Some(stored)) => *keymap = stored,
+        Ok(None) => {}
+        Err(err) => bevy::log::warn!("Using the default controls: {}", err),
+    }
+}
+
+/// Replaces the keyboard mappings of the bindable actions with the keymap.
+pub fn apply_keymap(
+    keymap: Res<Keymap>,
+    input_mapping: Option<ResMut<InputMapping<TopDownAction>>>,
+) {
+    let Some(mut input_mapping) = input_mapping else {
+        return;
+    };
+    let actions = BindableAction::ALL.map(|action| action.top_down_action());
+    let outdated = input_mapping
+        .get_mappings_as_slice()
+        .iter()
+        .filter(|item| actions.contains(&item.action))
+        .map(|item| ButtonMappingItem {
+            input: item.input.clone(),
+            action: item.action.clone(),
+        })
+        .collect::<Vec<_>>();
+    for item in &outdated {
+        input_mapping.remove_button_mapping(item);
+    }
+    for action in BindableAction::ALL {
+        for binding in keymap.bindings(action) {
+            if let Binding::Key(key) = binding {
+                input_mapping.add_button_mapping(ButtonMappingItem {
+                    input: UserButtonInput::KeyPressed(*key),
+                    action: action.top_down_action(),
+                });
+            }
+        }
+    }
+}
+
+/// Sends the actions of held gamepad buttons, like held keys do.
+pub fn gamepad_actions(
+    keymap: Res<Keymap>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    mut actions: EventWriter<input::ActionEvent<TopDownAction>>,
+) {
+    for action in BindableAction::ALL {
+        let pressed = keymap.bindings(action).iter().any(|binding| match binding {
+            Binding::Gamepad(button_type) => gamepads
+                .iter()
+                .any(|gamepad| gamepad_buttons.pressed(GamepadButton::new(gamepad, *button_type))),
+            Binding::Key(_) => false,
+        });
+        if pressed {
+            actions.send(input::ActionEvent {
+                action: action.top_down_action(),
+            });
+        }
+    }
+}
+
+/// Where the left stick points, it steers the player at any angle.
+#[derive(Resource, Default, Clone, Copy, PartialEq, Debug)]
+pub struct LeftStick(pub Vec2);
+
+/// Takes the stick of the last gamepad which isn't centered.
+pub fn read_left_stick(
+    gamepads: Res<Gamepads>,
+    gamepad_axes: Res<Axis<GamepadAxis>>,
+    mut left_stick: ResMut<LeftStick>,
+) {
+    left_stick.0 = Vec2::ZERO;
+    for gamepad in gamepads.iter() {
+        let stick = Vec2::new(
+            gamepad_axes
+                .get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickX))
+                .unwrap_or(0.0),
+            gamepad_axes
+                .get(GamepadAxis::new(gamepad, GamepadAxisType::LeftStickY))
+                .unwrap_or(0.0),
+        );
+        if stick != Vec2::ZERO {
+            left_stick.0 = stick;
+        }
+    }
+}
+
+pub struct KeymapPlugin;
+impl Plugin for KeymapPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.world.contains_resource::<KeymapStorage>() {
+            app.init_resource::<KeymapStorage>();
+        }
+        app.init_resource::<Keymap>()
+            .init_resource::<LeftStick>()
+            .add_systems(Startup, load_keymap)
+            .add_systems(Update, apply_keymap.run_if(resource_changed::<Keymap>))
+            .add_systems(
+                Update,
+                (gamepad_actions, read_left_stick)
+                    .run_if(in_state(GameState::InGame).and_then(in_state(PauseState::Running))),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use some_bevy_tools::controller_2d;
+
+    #[test]
+    fn stored_keymap_round_trip() {
+        let mut keymap = Keymap::default();
+        keymap
+            .rebind(BindableAction::Fire, Binding::Key(KeyCode::KeyJ))
+            .unwrap();
+        keymap
+            .rebind(
+                BindableAction::Fire,
+                Binding::Gamepad(GamepadButtonType::RightTrigger2),
+            )
+            .unwrap();
+        assert_eq!(
+            keymap.bindings(BindableAction::Fire),
+            &[
+                Binding::Gamepad(GamepadButtonType::RightTrigger2),
+                Binding::Key(KeyCode::KeyJ)
+            ]
+        );
+        assert_eq!(Keymap::parse(&keymap.to_string()).unwrap(), keymap);
+    }
+
+    #[test]
+    fn missing_actions_keep_defaults() {
+        let keymap = Keymap::parse("# Only fire\nfire = key KeyJ\n").unwrap();
+        assert_eq!(
+            keymap.bindings(BindableAction::Fire),
+            &[Binding::Key(KeyCode::KeyJ)]
+        );
+        assert_eq!(
+            keymap.bindings(BindableAction::MoveUp),
+            Keymap::default().bindings(BindableAction::MoveUp)
+        );
+    }
+
+    #[test]
+    fn report_invalid_lines() {
+        assert!(matches!(
+            Keymap::parse("jump = key Space"),
+            Err(KeymapError::UnknownAction { line: 1, .. })
+        ));
+        assert!(matches!(
+            Keymap::parse("\nfire = key NoSuchKey"),
+            Err(KeymapError::UnknownBinding {
+                line: 2,
+                source: BindingError::UnknownKey(_),
+                ..
+            })
+        ));
+        assert!(matches!(
+            Keymap::parse("fire = mouse Left"),
+            Err(KeymapError::UnknownBinding {
+                source: BindingError::InvalidFormat,
+                ..
+            })
+        ));
+        assert_eq!(
+            "gamepad Jump".parse::<Binding>(),
+            Err(BindingError::UnknownButton("Jump".to_string()))
+        );
+        assert!(matches!(
+            Keymap::parse("fire"),
+            Err(KeymapError::InvalidLine(1))
+        ));
+    }
+
+    #[test]
+    fn refuse_conflicting_binding() {
+        let mut keymap = Keymap::default();
+        let result = keymap.rebind(BindableAction::Fire, Binding::Key(KeyCode::KeyW));
+        assert!(matches!(
+            result,
+            Err(KeymapError::Conflict {
+                action: BindableAction::MoveUp,
+                ..
+            })
+        ));
+        assert_eq!(keymap, Keymap::default());
+        // Binding the same key again is fine.
+        keymap
+            .rebind(BindableAction::MoveUp, Binding::Key(KeyCode::KeyW))
+            .unwrap();
+    }
+
+    #[test]
+    fn conflicting_stored_keymap_falls_back_to_defaults() {
+        // Move up keeps its default W.
+        assert!(matches!(
+            Keymap::parse("fire = key KeyW"),
+            Err(KeymapError::Conflict {
+                action: BindableAction::MoveUp,
+                ..
+            })
+        ));
+        let game = crate::test_support::TestGame::with_setup(|app| {
+            app.insert_resource(KeymapStorage(Storage::Memory(Some(
+                "fire = key KeyW\n".to_string(),
+            ))));
+        });
+        assert_eq!(*game.app.world.resource::<Keymap>(), Keymap::default());
+    }
+
+    #[test]
+    fn bound_gamepad_button_moves_player() {
+        use bevy::input::gamepad::{
+            GamepadButtonChangedEvent, GamepadConnection, GamepadConnectionEvent, GamepadEvent,
+            GamepadInfo,
+        };
+        let mut game = crate::test_support::TestGame::new();
+        let gamepad = Gamepad::new(0);
+        game.app
+            .world
+            .send_event(GamepadEvent::Connection(GamepadConnectionEvent {
+                gamepad,
+                connection: GamepadConnection::Connected(GamepadInfo {
+                    name: "Test".to_string(),
+                }),
+            }));
+        game.step(1);
+        game.app
+            .world
+            .send_event(GamepadEvent::Button(GamepadButtonChangedEvent::new(
+                gamepad,
+                GamepadButtonType::DPadUp,
+                1.0,
+            )));
+        game.step(2);
+        let player = game.player();
+        assert_eq!(
+            game.app.world.get::<crate::ship::Thrust>(player).unwrap().0,
+            Vec2::Y
+        );
+    }
+
+    #[test]
+    fn keymap_replaces_input_mapping() {
+        let mut keymap = Keymap::default();
+        keymap
+            .rebind(BindableAction::MoveUp, Binding::Key(KeyCode::KeyI))
+            .unwrap();
+        let mut app = App::new();
+        app.insert_resource(keymap)
+            .add_systems(Startup, controller_2d::setup_top_down_mapping)
+            .add_systems(Update, apply_keymap);
+        app.update();
+
+        let input_mapping = app.world.resource::<InputMapping<TopDownAction>>();
+        let keys_for = |action: TopDownAction| {
+            input_mapping
+                .get_mappings_as_slice()
+                .iter()
+                .filter(|item| item.action == action)
+                .map(|item| item.input.clone())
+                .collect::<Vec<_>>()
+        };
+        assert!(
+            keys_for(TopDownAction::MoveUp) == vec![UserButtonInput::KeyPressed(KeyCode::KeyI)]
+        );
+        assert!(
+            keys_for(TopDownAction::Exit) == vec![UserButtonInput::KeyPressed(KeyCode::Escape)]
+        );
+    }
+}
@@ -0,0 +1,394 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::map_builder::{MapDraftError, TileType, TriggerAction};
+use crate::pickup::{LootTable, PickupKind};
+
+/// Health of a rock if its definition doesn't set one.
+pub const DEFAULT_ROCK_HEALTH: f32 = 10.0;
+
+/// Size multiplier of a trigger if its definition doesn't set one.
+pub const DEFAULT_TRIGGER_SIZE: f32 = 1.0;
+
+/// Damage per tick of a hazard if its definition doesn't set one.
+pub const DEFAULT_HAZARD_DAMAGE: f32 = 5.0;
+
+/// Seconds between the damage ticks of a hazard if its definition doesn't
+/// set them.
+pub const DEFAULT_HAZARD_TICK_INTERVAL: f32 = 0.5;
+
+/// Health of an enemy if its definition doesn't set one.
+pub const DEFAULT_ENEMY_HEALTH: f32 = 30.0;
+
+/// Distance an enemy patrols around its position if its definition doesn't
+/// set one.
+pub const DEFAULT_ENEMY_PATROL_RADIUS: f32 = 100.0;
+
+/// Health of a turret if its definition doesn't set one.
+pub const DEFAULT_TURRET_HEALTH: f32 = 40.0;
+
+/// Health of an objective if its definition doesn't set one.
+pub const DEFAULT_OBJECTIVE_HEALTH: f32 = 50.0;
+
+/// Waves of an encounter if its definition doesn't set them.
+pub const DEFAULT_ENCOUNTER_WAVES: u32 = 3;
+
+/// Enemies per wave of an encounter if its definition doesn't set them.
+pub const DEFAULT_ENCOUNTER_ENEMIES: u32 = 2;
+
+/// Seconds between the waves of an encounter if its definition doesn't set
+/// them.
+pub const DEFAULT_ENCOUNTER_DELAY: f32 = 2.0;
+
+/// Maps the characters of a map grid to the tiles they stand for.
+///
+/// A legend is written as one `tile <char> = <definition>` line per entry,
+/// where the definition is one of
+///
+/// ```text
+/// wall
+/// rock [health] [loot <pickup>:<chance>...]
+/// trigger <name> [size multiplier]
+/// single_trigger <name> [size multiplier]
+/// checkpoint
+/// hazard [damage per tick] [seconds per tick]
+/// weapon <blaster|scatter|rapid>
+/// pickup <health|gem> [amount]
+/// pickup <upgrade|key>
+/// enemy [health] [patrol radius]
+/// turret [health]
+/// encounter [waves] [enemies per wave] [seconds between waves]
+/// door
+/// spawn
+/// exit
+/// objective [health]
+/// ```
+///
+/// Shared legends are kept in `.legend` files and merged with the entries of
+/// each map, where the map entries win.
+#[derive(Clone, Default)]
+pub struct Legend {
+    tiles: BTreeMap<char, TileType>,
+}
+
+impl Legend {
+    pub fn get(&self, c: char) -> Option<TileType> {
+        self.tiles.get(&c).cloned()
+    }
+
+    pub fn into_tile_mapper(self) -> Box<dyn Fn(char) -> Option<TileType>> {
+        Box::new(move |c| self.get(c))
+    }
+
+    /// Adds all entries of `other`, replacing entries with the same character.
+    pub fn merge(&mut self, other: &Legend) {
+        self.tiles.extend(
+            other
+                .tiles
+                .iter()
+                .map(|(c, tile_type)| (*c, tile_type.clone())),
+        );
+    }
+
+    pub fn from_definitions(definitions: &HashMap<char, String>) -> Result<Self, MapDraftError> {
+        let tiles = definitions
+            .iter()
+            .map(|(c, definition)| Ok((*c, definition.parse()?)))
+            .collect::<Result<_, MapDraftError>>()?;
+        Ok(Self { tiles })
+    }
+}
+
+/// Returns true for the characters which mark an empty tile.
+pub fn is_empty_tile(c: char) -> bool {
+    c == '.' || c == ' '
+}
+
+/// Returns the character of a `tile <char>` key.
+pub fn legend_char(key: &str) -> Option<char> {
+    let mut chars = key.strip_prefix("tile ")?.trim().chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+impl FromStr for TileType {
+    type Err = MapDraftError;
+
+    fn from_str(definition: &str) -> Result<Self, Self::Err> {
+        let invalid = || MapDraftError::InvalidTileDefinition(definition.to_string());
+        let parse_number = |value: Option<&&str>, default: f32| match value {
+            Some(value) => value.parse::<f32>().map_err(|_| invalid()),
+            None => Ok(default),
+        };
+        let parts = definition.split_whitespace().collect::<Vec<_>>();
+        match parts.as_slice() {
+            ["wall"] => Ok(TileType::Wall),
+            ["checkpoint"] => Ok(TileType::Checkpoint),
+            ["weapon", kind] => Ok(TileType::Weapon(kind.parse()?)),
+            ["enemy", values @ ..] if values.len() <= 2 => Ok(TileType::Enemy(
+                parse_number(values.first(), DEFAULT_ENEMY_HEALTH)?,
+                parse_number(values.get(1), DEFAULT_ENEMY_PATROL_RADIUS)?,
+            )),
+            ["hazard", damage, tick_interval @ ..] if tick_interval.len() <= 1 => {
+                Ok(TileType::Hazard(
+                    parse_number(Some(damage), DEFAULT_HAZARD_DAMAGE)?,
+                    parse_number(tick_interval.first(), DEFAULT_HAZARD_TICK_INTERVAL)?,
+                ))
+            }
+            ["hazard"] => Ok(TileType::Hazard(
+                DEFAULT_HAZARD_DAMAGE,
+                DEFAULT_HAZARD_TICK_INTERVAL,
+            )),
+            ["door"] => Ok(TileType::Door),
+            ["exit"] => Ok(TileType::Exit),
+            ["objective", health @ ..] if health.len() <= 1 => Ok(TileType::Objective(
+                parse_number(health.first(), DEFAULT_OBJECTIVE_HEALTH)?,
+            )),
+            ["spawn"] => Ok(TileType::EnemySpawn),
+            ["encounter", values @ ..] if values.len() <= 3 => {
+                let parse_count = |value: Option<&&str>, default: u32| match value {
+                    Some(value) => value.parse::<u32>().map_err(|_| invalid()),
+                    None => Ok(default),
+                };
+                Ok(TileType::Encounter(
+                    parse_count(values.first(), DEFAULT_ENCOUNTER_WAVES)?,
+                    parse_count(values.get(1), DEFAULT_ENCOUNTER_ENEMIES)?,
+                    parse_number(values.get(2), DEFAULT_ENCOUNTER_DELAY)?,
+                ))
+            }
+            ["turret", health @ ..] if health.len() <= 1 => Ok(TileType::Turret(parse_number(
+                health.first(),
+                DEFAULT_TURRET_HEALTH,
+            )?)),
+            ["rock", values @ ..] => {
+                let (health, loot) = match values.iter().position(|value| *value == "loot") {
+                    Some(index) => (&values[..index], &values[index + 1..]),
+                    None => (values, &[][..]),
+                };
+                if health.len() > 1 {
+                    return Err(invalid());
+                }
+                Ok(TileType::Rock(
+                    parse_number(health.first(), DEFAULT_ROCK_HEALTH)?,
+                    LootTable::parse(loot.iter().copied())?,
+                ))
+            }
+            ["pickup", kind, amount @ ..] if amount.len() <= 1 => {
+                let kind = match (kind.parse()?, amount.first()) {
+                    (kind, None) => kind,
+                    (PickupKind::Health(_), Some(amount)) => {
+                        PickupKind::Health(amount.parse().map_err(|_| invalid())?)
+                    }
+                    (PickupKind::Gem(_), Some(amount)) => {
+                        PickupKind::Gem(amount.parse().map_err(|_| invalid())?)
+                    }
+                    _ => return Err(invalid()),
+                };
+                Ok(TileType::Pickup(kind))
+            }
+            ["trigger", trigger, size_multiplier @ ..] if size_multiplier.len() <= 1 => {
+                Ok(TileType::Trigger(
+                    TriggerAction(trigger.to_string()),
+                    parse_number(size_multiplier.first(), DEFAULT_TRIGGER_SIZE)?,
+                ))
+            }
+            ["single_trigger", trigger, size_multiplier @ ..] if size_multiplier.len() <= 1 => {
+                Ok(TileType::SingleTrigger(
+                    TriggerAction(trigger.to_string()),
+                    parse_number(size_multiplier.first(), DEFAULT_TRIGGER_SIZE)?,
+                ))
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for TileType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TileType::Wall => write!(f, "wall"),
+            TileType::Rock(health, loot) if loot.is_empty() => write!(f, "rock {}", health),
+            TileType::Rock(health, loot) => write!(f, "rock {} loot {}", health, loot),
+            TileType::Trigger(trigger, size_multiplier) => {
+                write!(f, "trigger {} {}", trigger, size_multiplier)
+            }
+            TileType::SingleTrigger(trigger, size_multiplier) => {
+                write!(f, "single_trigger {} {}", trigger, size_multiplier)
+            }
+            TileType::Checkpoint => write!(f, "checkpoint"),
+            TileType::Hazard(damage, tick_interval) => {
+                write!(f, "hazard {} {}", damage, tick_interval)
+            }
+            TileType::Weapon(kind) => write!(f, "weapon {}", kind),
+            TileType::Pickup(kind) => write!(f, "pickup {}", kind),
+            TileType::Turret(health) => write!(f, "turret {}", health),
+            TileType::Encounter(waves, enemies, delay) => {
+                write!(f, "encounter {} {} {}", waves, enemies, delay)
+            }
+            TileType::Door => write!(f, "door"),
+            TileType::Exit => write!(f, "exit"),
+            TileType::Objective(health) => write!(f, "objective {}", health),
+            TileType::EnemySpawn => write!(f, "spawn"),
+            TileType::Enemy(health, patrol_radius) => {
+                write!(f, "enemy {} {}", health, patrol_radius)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::weapon::WeaponKind;
+
+    fn legend(definitions: &[(char, &str)]) -> Legend {
+        let definitions = definitions
+            .iter()
+            .map(|(c, definition)| (*c, definition.to_string()))
+            .collect();
+        Legend::from_definitions(&definitions).unwrap()
+    }
+
+    #[test]
+    fn parse_definitions() {
+        let legend = legend(&[
+            ('X', "wall"),
+            ('O', "rock 20 loot health:0.5"),
+            ('1', "single_trigger Start 1.5"),
+            ('S', "weapon scatter"),
+            ('+', "pickup health 30"),
+        ]);
+        assert_eq!(legend.get('X'), Some(TileType::Wall));
+        assert_eq!(
+            legend.get('O'),
+            Some(TileType::Rock(
+                20.0,
+                LootTable::parse(["health:0.5"]).unwrap()
+            ))
+        );
+        assert_eq!(
+            legend.get('1'),
+            Some(TileType::SingleTrigger(
+                TriggerAction("Start".to_string()),
+                1.5
+            ))
+        );
+        assert_eq!(legend.get('S'), Some(TileType::Weapon(WeaponKind::Scatter)));
+        assert_eq!(
+            legend.get('+'),
+            Some(TileType::Pickup(PickupKind::Health(30.0)))
+        );
+        assert_eq!(legend.get('?'), None);
+    }
+
+    #[test]
+    fn missing_values_use_defaults() {
+        let defaults = [
+            (
+                "rock",
+                TileType::Rock(DEFAULT_ROCK_HEALTH, LootTable::default()),
+            ),
+            (
+                "trigger Go",
+                TileType::Trigger(TriggerAction("Go".to_string()), DEFAULT_TRIGGER_SIZE),
+            ),
+            (
+                "hazard",
+                TileType::Hazard(DEFAULT_HAZARD_DAMAGE, DEFAULT_HAZARD_TICK_INTERVAL),
+            ),
+            (
+                "hazard 8",
+                TileType::Hazard(8.0, DEFAULT_HAZARD_TICK_INTERVAL),
+            ),
+            (
+                "enemy",
+                TileType::Enemy(DEFAULT_ENEMY_HEALTH, DEFAULT_ENEMY_PATROL_RADIUS),
+            ),
+            ("turret", TileType::Turret(DEFAULT_TURRET_HEALTH)),
+            ("objective", TileType::Objective(DEFAULT_OBJECTIVE_HEALTH)),
+            (
+                "encounter 5",
+                TileType::Encounter(5, DEFAULT_ENCOUNTER_ENEMIES, DEFAULT_ENCOUNTER_DELAY),
+            ),
+        ];
+        for (definition, tile_type) in defaults {
+            assert_eq!(definition.parse::<TileType>().unwrap(), tile_type);
+        }
+    }
+
+    #[test]
+    fn map_entries_override_shared_ones() {
+        let mut merged = legend(&[('X', "wall"), ('C', "checkpoint")]);
+        merged.merge(&legend(&[('X', "door"), ('>', "exit")]));
+        assert_eq!(merged.get('X'), Some(TileType::Door));
+        assert_eq!(merged.get('C'), Some(TileType::Checkpoint));
+        assert_eq!(merged.get('>'), Some(TileType::Exit));
+    }
+
+    #[test]
+    fn legend_keys_and_empty_tiles() {
+        assert_eq!(legend_char("tile X"), Some('X'));
+        assert_eq!(legend_char("tile  ~ "), Some('~'));
+        assert_eq!(legend_char("tile XY"), None);
+        assert_eq!(legend_char("tile "), None);
+        assert_eq!(legend_char("width"), None);
+        assert!(is_empty_tile('.'));
+        assert!(is_empty_tile(' '));
+        assert!(!is_empty_tile('X'));
+    }
+
+    #[test]
+    fn tile_definition_round_trip() {
+        let source = std::fs::read_to_string("assets/maps/default.legend").unwrap();
+        let definitions = source
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(_, definition)| definition.trim())
+            .chain(["trigger Go 2", "single_trigger Go", "pickup gem 5"]);
+        for definition in definitions {
+            let tile_type = definition.parse::<TileType>().unwrap();
+            assert_eq!(
+                tile_type.to_string().parse::<TileType>().unwrap(),
+                tile_type
+            );
+        }
+    }
+
+    #[test]
+    fn report_invalid_definitions() {
+        for definition in [
+            "laser",
+            "",
+            "rock ten",
+            "rock 1 2",
+            "enemy 1 2 3",
+            "encounter 1.5",
+            "pickup key 3",
+            "trigger",
+        ] {
+            assert!(
+                matches!(
+                    definition.parse::<TileType>(),
+                    Err(MapDraftError::InvalidTileDefinition(_))
+                ),
+                "{definition}"
+            );
+        }
+        assert!(matches!(
+            "weapon laser".parse::<TileType>(),
+            Err(MapDraftError::UnknownWeapon(_))
+        ));
+        assert!(matches!(
+            "pickup coin".parse::<TileType>(),
+            Err(MapDraftError::UnknownPickup(_))
+        ));
+        let definitions = HashMap::from([('X', "wall".to_string()), ('?', "laser".to_string())]);
+        assert!(matches!(
+            Legend::from_definitions(&definitions),
+            Err(MapDraftError::InvalidTileDefinition(definition)) if definition == "laser"
+        ));
+    }
+}
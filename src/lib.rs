@@ -0,0 +1,405 @@
+use bevy::asset::AssetMetaCheck;
+use bevy::prelude::*;
+use bevy::sprite::Material2dPlugin;
+use bevy::sprite::MaterialMesh2dBundle;
+#[cfg(target_arch = "wasm32")]
+use bevy::window::WindowMode;
+use bevy_rapier2d::prelude::*;
+use bullet::BulletPlugin;
+use error_handler::GameError;
+use ship::ship_orientation;
+use some_bevy_tools::audio_loop::AudioLoopEvent;
+use some_bevy_tools::audio_loop::AudioLoopPlugin;
+use some_bevy_tools::camera_2d;
+use some_bevy_tools::controller_2d;
+use some_bevy_tools::despawn;
+use some_bevy_tools::health;
+use some_bevy_tools::input;
+use some_bevy_tools::loading;
+use some_bevy_tools::physics2d;
+use some_bevy_tools::trigger;
+use stars::StarMaterialSettings;
+
+pub mod assets;
+pub mod bullet;
+pub mod encounter;
+pub mod enemy;
+pub mod error_handler;
+pub mod faction;
+pub mod goal;
+pub mod keymap;
+pub mod legend;
+pub mod map_asset;
+pub mod map_builder;
+pub mod maps;
+pub mod menu;
+pub mod pickup;
+pub mod progression;
+pub mod random;
+pub mod replay;
+pub mod respawn;
+pub mod save;
+pub mod script;
+pub mod ship;
+pub mod stars;
+pub mod storage;
+pub mod turret;
+pub mod weapon;
+
+#[cfg(test)]
+pub(crate) mod test_support;
+
+/// Builds the game and runs it.
+pub fn run() {
+    let mut app = App::new();
+    app.insert_resource(RapierConfiguration {
+        gravity: Vec2::new(0.0, 0.0),
+        ..Default::default()
+    })
+    .insert_resource(AssetMetaCheck::Never);
+    match replay::ReplayMode::from_args(std::env::args().skip(1)) {
+        Ok(replay_mode) => app.insert_resource(replay_mode),
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    // Enable fullscreen in wasm
+    #[cfg(target_arch = "wasm32")]
+    app.add_plugins(DefaultPlugins.set(WindowPlugin {
+        primary_window: Some(Window {
+            title: "Some Bevy Game".to_string(),
+            //mode: WindowMode::BorderlessFullscreen,
+            ..Default::default()
+        }),
+        ..Default::default()
+    }));
+    #[cfg(not(target_arch = "wasm32"))]
+    app.add_plugins(DefaultPlugins.set(WindowPlugin {
+        primary_window: Some(Window {
+            title: "Some Bevy Game".to_string(),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }));
+    app.add_plugins(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(1.0))
+        .add_plugins(loading::LoadingPlugin(
+            GameState::Loading,
+            GameState::MainMenu,
+        ))
+        .add_plugins(loading::LoadPluginAssets(
+            assets::ImageAssets::default(),
+            GameState::Loading,
+        ))
+        .add_plugins(loading::LoadPluginAssets(
+            assets::MusicAssets::default(),
+            GameState::Loading,
+        ))
+        .add_plugins(loading::LoadPluginAssets(
+            assets::MapAssets::default(),
+            GameState::Loading,
+        ))
+        .add_plugins(camera_2d::Camera2DPlugin)
+        .add_plugins(controller_2d::TopDownControllerPlugin)
+        .add_plugins(Material2dPlugin::<stars::StarMaterial>::default())
+        .add_plugins(AudioLoopPlugin)
+        .add_plugins(GamePlugin)
+        .run();
+}
+
+/// The game logic without rendering, audio, input and asset loading, so
+/// it can also run headless in tests.
+pub struct GamePlugin;
+
+impl Plugin for GamePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(StarMaterialSettings::default())
+            .init_resource::<InGameState>()
+            .add_plugins(map_asset::MapAssetPlugin)
+            .add_plugins(despawn::CleanupPlugin(GameState::InGame))
+            // Triggers are only meant for the player, enemies would use up
+            // single triggers otherwise.
+            .add_plugins(trigger::PhysicsTriggerPlugin::<
+                ship::Player,
+                map_builder::TriggerAction,
+            >::default())
+            .add_plugins(BulletPlugin)
+            .init_state::<GameState>()
+            .add_plugins(keymap::KeymapPlugin)
+            .add_plugins(replay::ReplayPlugin)
+            .add_plugins(save::SavePlugin)
+            .add_plugins(progression::ProgressionPlugin)
+            .add_plugins(goal::GoalPlugin)
+            .add_plugins(menu::MenuPlugin)
+            .add_plugins(respawn::RespawnPlugin)
+            .add_plugins(weapon::WeaponPlugin)
+            .add_plugins(pickup::PickupPlugin)
+            .add_plugins(enemy::EnemyPlugin)
+            .add_plugins(turret::TurretPlugin)
+            .add_plugins(encounter::EncounterPlugin)
+            .add_systems(
+                OnEnter(GameState::InGame),
+                (startup_ingame.pipe(error_handler::error_handler), show_logo)
+                    .after(replay::start_run),
+            )
+            .add_systems(
+                Update,
+                (
+                    (
+                        user_event_handler
+                            .after(input::input_mapping_system::<controller_2d::TopDownAction>)
+                            .after(replay::play_replay),
+                        ship_orientation,
+                    )
+                        .chain(),
+                    (
+                        script::start_scripts.pipe(error_handler::error_handler),
+                        script::start_encounter_scripts.after(encounter::run_encounters),
+                        script::run_scripts.pipe(error_handler::error_handler),
+                    )
+                        .chain(),
+                    ship::thrust_controller,
+                    stars::update_stars,
+                    map_asset::hot_reload_maps,
+                )
+                    .run_if(
+                        in_state(GameState::InGame).and_then(in_state(menu::PauseState::Running)),
+                    ),
+            );
+    }
+}
+
+#[derive(States, PartialEq, Eq, Debug, Default, Hash, Clone, Copy)]
+pub enum GameState {
+    #[default]
+    Loading,
+    MainMenu,
+    Controls,
+    LevelSelect,
+    InGame,
+    GameOver,
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn startup_ingame(
+    mut commands: Commands,
+    image_assets: Res<assets::ImageAssets>,
+    music_assets: Res<assets::MusicAssets>,
+    map_assets: Res<assets::MapAssets>,
+    loaded_maps: Res<Assets<map_asset::MapAsset>>,
+    mut in_game_state: ResMut<InGameState>,
+    mut star_settings: ResMut<StarMaterialSettings>,
+    mut audio_events: EventWriter<AudioLoopEvent>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<stars::StarMaterial>>,
+    save_game: Res<save::SaveGame>,
+    replay_mode: Res<replay::ReplayMode>,
+    start_level: Res<progression::StartLevel>,
+) -> Result<(), GameError> {
+    // Start over when the game is restarted.
+    *in_game_state = InGameState::default();
+    *star_settings = StarMaterialSettings::default();
+
+    // Replays always start from the beginning, like they were recorded.
+    let replay_off = matches!(*replay_mode, replay::ReplayMode::Off);
+    let (level, position, health) = match start_level.0 {
+        Some(level) if replay_off => (level, Vec2::ZERO, respawn::PLAYER_HEALTH),
+        _ if replay_off && save_game.skips_tutorial() => {
+            (save_game.level, save_game.checkpoint, save_game.health)
+        }
+        _ => (maps::Level::Tutorial, Vec2::ZERO, respawn::PLAYER_HEALTH),
+    };
+
+    let player = commands
+        .spawn((
+            ship::ShipBundle {
+                sprite_bundle: SpriteBundle {
+                    texture: image_assets.ship.clone(),
+                    sprite: Sprite {
+                        custom_size: Some(Vec2::new(50.0, 50.0)),
+                        ..default()
+                    },
+                    transform: Transform::from_translation(position.extend(0.0)),
+                    ..default()
+                },
+                physics_bundle: physics2d::PhysicsBundle::dynamic_rectangle(50.0, 50.0),
+                acceleration: physics2d::Acceleration::new(1000.0, 300.0),
+                direction: ship::Direction::new(Vec2::Y),
+                thrust: ship::Thrust::default(),
+                health: health::Health::new(0.0, respawn::PLAYER_HEALTH)
+                    .with_current(health.min(respawn::PLAYER_HEALTH)),
+                faction: faction::Faction::Player,
+                weapon: weapon::Weapon::default(),
+                ship: ship::Ship,
+            },
+            despawn::Cleanup(GameState::InGame),
+            controller_2d::SimpleTopDownController::new(10.0),
+            ship::Player,
+        ))
+        .id();
+
+    let map_id = maps::spawn_level(
+        &mut commands,
+        level,
+        &map_assets,
+        &loaded_maps,
+        &image_assets,
+        Vec2::ZERO,
+    )?;
+    in_game_state.active_map = Some(map_id);
+    in_game_state.level = level;
+    in_game_state.checkpoint = position;
+
+    let star_material = materials.add(stars::StarMaterial::default());
+
+    commands
+        .spawn((
+            Camera2dBundle::default(),
+            despawn::Cleanup(GameState::InGame),
+            camera_2d::Camera2DController::new_follow_with_speed(player, 100.0),
+            InheritedVisibility::VISIBLE,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                Name::new("Stars".to_string()),
+                MaterialMesh2dBundle {
+                    mesh: meshes
+                        .add(Mesh::from(Rectangle {
+                            half_size: Vec2::new(1280.0, 1280.0),
+                        }))
+                        .into(),
+                    transform: Transform::default()
+                        .with_scale(Vec3::splat(1280.0))
+                        .with_translation(Vec3::new(0.0, 0.0, -1.0)),
+                    material: star_material.clone(),
+                    ..default()
+                },
+            ));
+        });
+
+    commands.spawn((
+        AudioSourceBundle {
+            source: music_assets.space.clone(),
+            ..default()
+        },
+        despawn::Cleanup(GameState::InGame),
+    ));
+    audio_events.send(AudioLoopEvent::EndPositionImmediate(
+        19.2,
+        music_assets.space.clone(),
+    ));
+
+    Ok(())
+}
+
+#[derive(Component, Default)]
+pub struct StaticWall;
+
+#[derive(Resource)]
+pub struct InGameState {
+    pub block_controls: bool,
+
+    /// Id of the map the player is currently in.
+    pub active_map: Option<uuid::Uuid>,
+    pub level: maps::Level,
+
+    /// Where the center of the active map is.
+    pub map_origin: Vec2,
+
+    /// Where the player respawns after dying.
+    pub checkpoint: Vec2,
+    pub lives: u32,
+
+    /// Value of the gems collected in this run.
+    pub score: u32,
+}
+
+impl Default for InGameState {
+    fn default() -> Self {
+        Self {
+            block_controls: false,
+            active_map: None,
+            level: maps::Level::default(),
+            map_origin: Vec2::ZERO,
+            checkpoint: Vec2::ZERO,
+            lives: respawn::PLAYER_LIVES,
+            score: 0,
+        }
+    }
+}
+
+/// Combines the pressed directions and the left stick into the thrust and
+/// target direction of the player, so ships can fly at any angle.
+#[allow(clippy::type_complexity)]
+fn user_event_handler(
+    mut controller_events: EventReader<input::ActionEvent<controller_2d::TopDownAction>>,
+    mut bullet_events: EventWriter<bullet::ShootBullet>,
+    left_stick: Res<keymap::LeftStick>,
+    mut query: Query<
+        (Entity, &mut ship::Thrust, &mut ship::Direction),
+        (
+            With<controller_2d::SimpleTopDownController>,
+            Without<respawn::Respawning>,
+        ),
+    >,
+    in_game_state: Res<InGameState>,
+) {
+    if in_game_state.block_controls {
+        return;
+    }
+    if let Ok((ship_entity, mut thrust, mut direction)) = query.get_single_mut() {
+        let mut movement = Vec2::ZERO;
+        for action in controller_events.read() {
+            match action.action {
+                controller_2d::TopDownAction::MoveUp => movement.y += 1.0,
+                controller_2d::TopDownAction::MoveDown => movement.y -= 1.0,
+                controller_2d::TopDownAction::MoveLeft => movement.x -= 1.0,
+                controller_2d::TopDownAction::MoveRight => movement.x += 1.0,
+                controller_2d::TopDownAction::Action => {
+                    bullet_events.send(bullet::ShootBullet { ship: ship_entity });
+                }
+                _ => {}
+            }
+        }
+        // Keys give full thrust in any of the eight directions.
+        movement = movement.clamp(Vec2::NEG_ONE, Vec2::ONE).normalize_or_zero();
+        if left_stick.0 != Vec2::ZERO {
+            movement = left_stick.0.clamp_length_max(1.0);
+        }
+        thrust.0 = movement;
+        direction.turn_to(movement);
+    }
+}
+
+#[derive(Component)]
+pub struct Logo;
+
+pub fn show_logo(mut commands: Commands, image_assets: Res<assets::ImageAssets>) {
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    ..default()
+                },
+                ..default()
+            },
+            despawn::Cleanup(GameState::InGame),
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                ImageBundle {
+                    image: UiImage::new(image_assets.logo_overlay.clone()),
+                    style: Style {
+                        width: Val::Percent(100.0),
+                        height: Val::Percent(100.0),
+                        ..default()
+                    },
+                    visibility: Visibility::Hidden,
+                    ..default()
+                },
+                Logo,
+            ));
+        });
+}
@@ -0,0 +1,555 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use bevy::asset::{
+    io::Reader, AssetLoader, AsyncReadExt, LoadContext, ParseAssetPathError, ReadAssetBytesError,
+};
+use bevy::prelude::*;
+use bevy::utils::BoxedFuture;
+use thiserror::Error;
+
+use crate::assets::ImageAssets;
+use crate::error_handler::{log_unless_critical, GameError};
+use crate::legend::{is_empty_tile, legend_char, Legend};
+use crate::map_builder::{Map, MapDraft, MapDraftError, SpawnedMap, TileType, UnloadMapExt};
+use crate::script::{parse_scripts, ScriptError, Scripts};
+
+/// A map loaded from a `.map` file.
+///
+/// The file starts with a header of `key = value` lines, followed by a `---`
+/// separator and the tile grid, top row first:
+///
+/// ```text
+/// # Comment
+/// width = 29
+/// height = 25
+/// center = 2, 2
+/// merge_walls = true
+/// legend = default.legend
+/// script = tutorial.script
+/// tile O = rock 20
+/// tile 1 = single_trigger SimplyForward 1.1
+/// ---
+/// XXXXX
+/// X.1.X
+/// ...
+/// ```
+///
+/// The optional `legend` file is loaded relative to the map and its `tile`
+/// entries are overridden by the ones of the map, see [`Legend`]. Characters
+/// without a `tile` entry (by convention `.`) stay empty. The optional `script`
+/// file holds the scripts the triggers of the map start, see
+/// [`parse_scripts`].
+#[derive(Asset, TypePath, Debug, Clone)]
+pub struct MapAsset {
+    pub width: u32,
+    pub height: u32,
+    pub center: (i32, i32),
+    pub merge_walls: bool,
+    pub legend_file: Option<String>,
+    pub shared_legend: HashMap<char, String>,
+    pub legend: HashMap<char, String>,
+    pub script_file: Option<String>,
+    pub scripts: Scripts,
+    pub rows: Vec<String>,
+
+    /// Line of the first row in the map file, used to report errors.
+    pub grid_line: usize,
+}
+
+#[derive(Error, Debug)]
+pub enum MapAssetError {
+    #[error("could not read map file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("map file is not valid UTF-8: {0}")]
+    Utf8(#[from] std::str::Utf8Error),
+
+    #[error("invalid legend path: {0}")]
+    LegendPath(#[from] ParseAssetPathError),
+
+    #[error("could not read legend file: {0}")]
+    LegendFile(#[from] ReadAssetBytesError),
+
+    #[error("could not read script file: {0}")]
+    ScriptFile(ReadAssetBytesError),
+
+    #[error("invalid script file: {0}")]
+    Script(#[from] ScriptError),
+
+    #[error("line {0}: expected `key = value`")]
+    InvalidHeaderLine(usize),
+
+    #[error("line {line}: unknown header key `{key}`")]
+    UnknownKey { line: usize, key: String },
+
+    #[error("line {line}: invalid value `{value}`")]
+    InvalidValue { line: usize, value: String },
+
+    #[error("header field `{0}` is missing")]
+    MissingField(&'static str),
+
+    #[error("`---` separator before the tile grid is missing")]
+    MissingGrid,
+
+    #[error("expected {expected} rows but found {found}")]
+    RowCountMismatch { expected: u32, found: u32 },
+
+    #[error("line {line}: expected {expected} columns but found {found}")]
+    RowWidthMismatch {
+        line: usize,
+        expected: u32,
+        found: u32,
+    },
+}
+
+impl MapAsset {
+    pub fn parse(source: &str) -> Result<Self, MapAssetError> {
+        let mut width = None;
+        let mut height = None;
+        let mut center = None;
+        let mut merge_walls = false;
+        let mut legend_file = None;
+        let mut script_file = None;
+        let mut legend = HashMap::new();
+        let mut lines = source.lines().enumerate();
+        let mut has_grid = false;
+
+        for (index, line) in lines.by_ref() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line == "---" {
+                has_grid = true;
+                break;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(MapAssetError::InvalidHeaderLine(line_number))?;
+            let (key, value) = (key.trim(), value.trim());
+            let invalid_value = || MapAssetError::InvalidValue {
+                line: line_number,
+                value: value.to_string(),
+            };
+            match key {
+                "width" => width = Some(value.parse::<u32>().map_err(|_| invalid_value())?),
+                "height" => height = Some(value.parse::<u32>().map_err(|_| invalid_value())?),
+                "center" => {
+                    let (x, y) = value.split_once(',').ok_or_else(invalid_value)?;
+                    center = Some((
+                        x.trim().parse::<i32>().map_err(|_| invalid_value())?,
+                        y.trim().parse::<i32>().map_err(|_| invalid_value())?,
+                    ));
+                }
+                "merge_walls" => {
+                    merge_walls = value.parse::<bool>().map_err(|_| invalid_value())?
+                }
+                "legend" => legend_file = Some(value.to_string()),
+                "script" => script_file = Some(value.to_string()),
+                _ => match legend_char(key) {
+                    Some(c) => {
+                        legend.insert(c, value.to_string());
+                    }
+                    None => {
+                        return Err(MapAssetError::UnknownKey {
+                            line: line_number,
+                            key: key.to_string(),
+                        })
+                    }
+                },
+            }
+        }
+        if !has_grid {
+            return Err(MapAssetError::MissingGrid);
+        }
+        let width = width.ok_or(MapAssetError::MissingField("width"))?;
+        let height = height.ok_or(MapAssetError::MissingField("height"))?;
+        let center = center.ok_or(MapAssetError::MissingField("center"))?;
+
+        let mut rows: Vec<(usize, &str)> = lines.map(|(index, line)| (index + 1, line)).collect();
+        while rows.last().is_some_and(|(_, row)| row.trim().is_empty()) {
+            rows.pop();
+        }
+        if rows.len() as u32 != height {
+            return Err(MapAssetError::RowCountMismatch {
+                expected: height,
+                found: rows.len() as u32,
+            });
+        }
+        let grid_line = rows.first().map_or(0, |(line, _)| *line);
+        for (line, row) in &rows {
+            let found = row.chars().count() as u32;
+            if found != width {
+                return Err(MapAssetError::RowWidthMismatch {
+                    line: *line,
+                    expected: width,
+                    found,
+                });
+            }
+        }
+
+        Ok(Self {
+            width,
+            height,
+            center,
+            merge_walls,
+            legend_file,
+            shared_legend: HashMap::new(),
+            legend,
+            script_file,
+            scripts: Scripts::new(),
+            rows: rows.into_iter().map(|(_, row)| row.to_string()).collect(),
+            grid_line,
+        })
+    }
+
+    pub fn legend(&self) -> Result<Legend, MapDraftError> {
+        let mut legend = Legend::from_definitions(&self.shared_legend)?;
+        legend.merge(&Legend::from_definitions(&self.legend)?);
+        Ok(legend)
+    }
+
+    pub fn build_map(&self) -> Result<Map, MapDraftError> {
+        let draft = MapDraft::from_str(
+            &self.rows.concat(),
+            self.width,
+            self.height,
+            self.legend()?.into_tile_mapper(),
+        )?;
+        Ok(draft
+            .to_map(self.center)
+            .with_merged_walls(self.merge_walls))
+    }
+
+    /// Builds the map after validating it.
+    ///
+    /// Problems which aren't critical are only logged.
+    pub fn build_validated_map(&self) -> Result<Map, GameError> {
+        for problem in self.validate() {
+            log_unless_critical(problem)?;
+        }
+        Ok(self.build_map()?)
+    }
+
+    /// Checks the map for mistakes which still let it be built.
+    ///
+    /// All problems are returned at once, so a designer can fix them in one
+    /// go. Triggers, checkpoints and pickups count as reachable if there is a path
+    /// without walls from the center to them. Every trigger needs a script with
+    /// the name of its action.
+    pub fn validate(&self) -> Vec<MapDraftError> {
+        if self.width == 0 || self.height == 0 || self.rows.is_empty() {
+            return vec![MapDraftError::EmptyMap];
+        }
+        let legend = match self.legend() {
+            Ok(legend) => legend,
+            Err(err) => return vec![err],
+        };
+        let mut problems = Vec::new();
+
+        let grid: Vec<Vec<char>> = self.rows.iter().map(|row| row.chars().collect()).collect();
+        for (row_index, row) in grid.iter().enumerate() {
+            for (column, c) in row.iter().enumerate() {
+                if !is_empty_tile(*c) && legend.get(*c).is_none() {
+                    problems.push(MapDraftError::UnknownTile {
+                        line: self.grid_line + row_index,
+                        column: column + 1,
+                        tile: *c,
+                    });
+                }
+            }
+        }
+
+        let (center_x, center_y) = self.center;
+        if center_x < 0
+            || center_y < 0
+            || center_x as u32 >= self.width
+            || center_y as u32 >= self.height
+        {
+            problems.push(MapDraftError::CenterOutOfBounds {
+                x: center_x,
+                y: center_y,
+                width: self.width,
+                height: self.height,
+            });
+            return problems;
+        }
+
+        // Rows are stored top first, while map coordinates start at the bottom.
+        let is_wall = |column: usize, row: usize| {
+            matches!(
+                grid.get(row).and_then(|tiles| tiles.get(column)),
+                Some(c) if matches!(legend.get(*c), Some(TileType::Wall))
+            )
+        };
+        let mut reached = vec![vec![false; self.width as usize]; self.height as usize];
+        let mut open = vec![(
+            center_x as usize,
+            (self.height - 1) as usize - center_y as usize,
+        )];
+        while let Some((column, row)) = open.pop() {
+            if row >= grid.len() || column >= grid[row].len() || reached[row][column] {
+                continue;
+            }
+            if is_wall(column, row) {
+                continue;
+            }
+            reached[row][column] = true;
+            open.push((column + 1, row));
+            open.push((column, row + 1));
+            if column > 0 {
+                open.push((column - 1, row));
+            }
+            if row > 0 {
+                open.push((column, row - 1));
+            }
+        }
+        for (row_index, row) in grid.iter().enumerate() {
+            for (column, c) in row.iter().enumerate() {
+                let is_trigger = matches!(
+                    legend.get(*c),
+                    Some(
+                        TileType::Trigger(..)
+                            | TileType::SingleTrigger(..)
+                            | TileType::Checkpoint
+                            | TileType::Weapon(_)
+                            | TileType::Pickup(_)
+                            | TileType::Encounter(..)
+                            | TileType::Exit
+                    )
+                );
+                if is_trigger && !reached[row_index][column] {
+                    problems.push(MapDraftError::UnreachableTrigger {
+                        line: self.grid_line + row_index,
+                        column: column + 1,
+                    });
+                }
+                if let Some(TileType::Trigger(action, _) | TileType::SingleTrigger(action, _)) =
+                    legend.get(*c)
+                {
+                    if !self.scripts.contains_key(&action.0) {
+                        problems.push(MapDraftError::MissingScript {
+                            line: self.grid_line + row_index,
+                            column: column + 1,
+                            action,
+                        });
+                    }
+                }
+            }
+        }
+        problems
+    }
+}
+
+/// Parses the `tile <char> = <definition>` lines of a shared `.legend` file.
+pub fn parse_legend_file(source: &str) -> Result<HashMap<char, String>, MapAssetError> {
+    let mut legend = HashMap::new();
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (key, value) = line
+            .split_once('=')
+            .ok_or(MapAssetError::InvalidHeaderLine(line_number))?;
+        let c = legend_char(key.trim()).ok_or_else(|| MapAssetError::UnknownKey {
+            line: line_number,
+            key: key.trim().to_string(),
+        })?;
+        legend.insert(c, value.trim().to_string());
+    }
+    Ok(legend)
+}
+
+/// Respawns every map built from a [`MapAsset`] which changed on disk.
+///
+/// The old map is unloaded and the new version is spawned with the same id at
+/// the same center. If the changed map can't be built, the old one stays.
+pub fn hot_reload_maps(
+    mut commands: Commands,
+    mut asset_events: EventReader<AssetEvent<MapAsset>>,
+    loaded_maps: Res<Assets<MapAsset>>,
+    image_assets: Res<ImageAssets>,
+    spawned_maps: Query<&SpawnedMap>,
+) {
+    for event in asset_events.read() {
+        let AssetEvent::Modified { id } = event else {
+            continue;
+        };
+        let Some(map_asset) = loaded_maps.get(*id) else {
+            continue;
+        };
+        for spawned_map in spawned_maps.iter() {
+            if spawned_map.source != Some(*id) {
+                continue;
+            }
+            let mut map = match map_asset.build_validated_map() {
+                Ok(map) => map.with_source(*id),
+                Err(err) => {
+                    bevy::log::error!("Could not reload map: {}", err);
+                    continue;
+                }
+            };
+            bevy::log::info!("Reloading map {}", spawned_map.id);
+            map.id = spawned_map.id;
+            commands.unload_map(map.id);
+            map.spawn_tiles(&mut commands, &image_assets, spawned_map.center);
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MapAssetLoader;
+
+impl AssetLoader for MapAssetLoader {
+    type Asset = MapAsset;
+    type Settings = ();
+    type Error = MapAssetError;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<MapAsset, MapAssetError>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let mut map = MapAsset::parse(std::str::from_utf8(&bytes)?)?;
+            if let Some(legend_file) = &map.legend_file {
+                let path = load_context.asset_path().resolve_embed(legend_file)?;
+                let bytes = load_context.read_asset_bytes(path).await?;
+                map.shared_legend = parse_legend_file(std::str::from_utf8(&bytes)?)?;
+            }
+            if let Some(script_file) = &map.script_file {
+                let path = load_context.asset_path().resolve_embed(script_file)?;
+                let bytes = load_context
+                    .read_asset_bytes(path)
+                    .await
+                    .map_err(MapAssetError::ScriptFile)?;
+                map.scripts = parse_scripts(std::str::from_utf8(&bytes)?)?;
+            }
+            Ok(map)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["map"]
+    }
+}
+
+pub struct MapAssetPlugin;
+impl Plugin for MapAssetPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<MapAsset>()
+            .init_asset_loader::<MapAssetLoader>();
+    }
+}
+
+/// Reads a map file with its legend and script files directly from disk,
+/// without the asset server.
+pub fn read_map_file(path: &Path) -> Result<MapAsset, MapAssetError> {
+    let mut map_asset = MapAsset::parse(&std::fs::read_to_string(path)?)?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+    if let Some(legend_file) = &map_asset.legend_file {
+        map_asset.shared_legend =
+            parse_legend_file(&std::fs::read_to_string(directory.join(legend_file))?)?;
+    }
+    if let Some(script_file) = &map_asset.script_file {
+        map_asset.scripts = parse_scripts(&std::fs::read_to_string(directory.join(script_file))?)?;
+    }
+    Ok(map_asset)
+}
+
+#[cfg(test)]
+pub(crate) fn read_map_asset(name: &str) -> MapAsset {
+    read_map_file(Path::new(&format!("assets/maps/{}.map", name))).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENCLOSED_TRIGGER: &str = "\
+width = 5
+height = 4
+center = 1, 1
+tile X = wall
+tile 1 = trigger SimplyForward
+---
+XXXXX
+X.X1X
+X.XXX
+XXXXX
+";
+
+    #[test]
+    fn built_in_maps_are_valid() {
+        assert!(read_map_asset("tutorial").validate().is_empty());
+        assert!(read_map_asset("level_1").validate().is_empty());
+    }
+
+    #[test]
+    fn report_enclosed_trigger() {
+        let problems = MapAsset::parse(ENCLOSED_TRIGGER).unwrap().validate();
+        assert!(matches!(
+            problems.as_slice(),
+            [
+                MapDraftError::UnreachableTrigger { line: 8, column: 4 },
+                MapDraftError::MissingScript {
+                    line: 8,
+                    column: 4,
+                    ..
+                }
+            ]
+        ));
+    }
+
+    #[test]
+    fn report_unknown_tile_and_center_out_of_bounds() {
+        let source = ENCLOSED_TRIGGER
+            .replace("center = 1, 1", "center = 5, 1")
+            .replace("X.XXX", "X.X?X");
+        let problems = MapAsset::parse(&source).unwrap().validate();
+        assert!(matches!(
+            problems.as_slice(),
+            [
+                MapDraftError::UnknownTile {
+                    line: 9,
+                    column: 4,
+                    tile: '?'
+                },
+                MapDraftError::CenterOutOfBounds { x: 5, y: 1, .. }
+            ]
+        ));
+    }
+
+    #[test]
+    fn report_row_width_with_line() {
+        let source = ENCLOSED_TRIGGER.replace("X.XXX", "X.XXXX");
+        assert!(matches!(
+            MapAsset::parse(&source),
+            Err(MapAssetError::RowWidthMismatch {
+                line: 9,
+                expected: 5,
+                found: 6
+            })
+        ));
+    }
+
+    #[test]
+    fn empty_str_array_is_an_error() {
+        assert!(matches!(
+            MapDraft::from_str_array(&[], Box::new(|_| None)),
+            Err(MapDraftError::EmptyMap)
+        ));
+    }
+
+    #[test]
+    fn str_length_counts_chars() {
+        assert!(MapDraft::from_str("äöüß", 2, 2, Box::new(|_| None)).is_ok());
+    }
+}
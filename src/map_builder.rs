@@ -1,60 +1,187 @@
 use crate::GameState;
-use crate::{assets::ImageAssets, StaticWall};
+use crate::{
+    assets::ImageAssets,
+    bullet::Damager,
+    encounter::{Door, EncounterTrigger, EnemySpawnPoint, Wave},
+    enemy,
+    goal::{Exit, Objective},
+    legend::DEFAULT_ENEMY_HEALTH,
+    map_asset::MapAsset,
+    pickup::{self, Loot, LootTable, PickupKind},
+    respawn::Checkpoint,
+    turret,
+    weapon::{WeaponKind, WeaponPickup},
+    StaticWall,
+};
+use bevy::ecs::system::Command;
 use bevy::prelude::*;
-use core::marker::Copy;
+use bevy_rapier2d::prelude::ColliderDisabled;
 use some_bevy_tools::health::Health;
 use some_bevy_tools::{despawn, physics2d, trigger};
+use std::collections::HashSet;
+use std::fmt;
 use thiserror::Error;
 use uuid::Uuid;
 
+/// Marks every entity which belongs to the map with the given id.
 #[derive(Component)]
-#[allow(dead_code)]
-pub struct TileMarker(Uuid);
+pub struct TileMarker(pub Uuid);
 
-#[derive(Clone, Copy)]
-pub enum TileType<T: Clone + Copy> {
+/// Despawns all tiles and triggers of the map with the given id.
+pub struct UnloadMap(pub Uuid);
+
+impl Command for UnloadMap {
+    fn apply(self, world: &mut World) {
+        let entities = world
+            .query::<(Entity, &TileMarker)>()
+            .iter(world)
+            .filter(|(_, tile_marker)| tile_marker.0 == self.0)
+            .map(|(entity, _)| entity)
+            .collect::<Vec<_>>();
+        bevy::log::info!("Unloading map {} ({} entities)", self.0, entities.len());
+        for entity in entities {
+            // Children of tiles despawned earlier are gone already.
+            if let Some(entity) = world.get_entity_mut(entity) {
+                entity.despawn_recursive();
+            }
+        }
+    }
+}
+
+pub trait UnloadMapExt {
+    fn unload_map(&mut self, id: Uuid);
+}
+
+impl UnloadMapExt for Commands<'_, '_> {
+    fn unload_map(&mut self, id: Uuid) {
+        self.add(UnloadMap(id));
+    }
+}
+
+/// Marks a spawned map, so it can be found again by its id.
+///
+/// Maps built from a [`MapAsset`] remember their source and are respawned
+/// at the same center when the asset changes.
+#[derive(Component)]
+pub struct SpawnedMap {
+    pub id: Uuid,
+    pub source: Option<AssetId<MapAsset>>,
+    pub center: Vec2,
+}
+
+/// A rock of a map, which can be shot to pieces.
+#[derive(Component, Default)]
+pub struct Rock;
+
+/// A trigger of a map, which starts the script with the name of its action
+/// when the player flies into it.
+#[derive(Component, Clone, Default, PartialEq, Eq, Debug)]
+pub struct TriggerAction(pub String);
+
+impl fmt::Display for TriggerAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub enum TileType {
     Wall,
-    Rock,
-    _Trigger(T, f32),
-    SingleTrigger(T, f32),
+    /// Can be shot with the given health and drops from its loot table once
+    /// destroyed.
+    Rock(f32, LootTable),
+    Trigger(TriggerAction, f32),
+    SingleTrigger(TriggerAction, f32),
+    /// The player respawns here after dying once it passed it.
+    Checkpoint,
+    /// Damages everything on it by the first value every time the number of
+    /// seconds of the second value passed.
+    Hazard(f32, f32),
+    /// Gives the weapon to the ship which flies through it.
+    Weapon(WeaponKind),
+    /// Collected by the player who flies through it.
+    Pickup(PickupKind),
+    /// An enemy ship with the health of the first value, which patrols a
+    /// square with the second value as distance from its center.
+    Enemy(f32, f32),
+    /// A fixed gun with the given health which shoots at the player.
+    Turret(f32),
+    /// Starts an encounter of as many waves as the first value, each with
+    /// as many enemies as the second value, spawning the number of seconds
+    /// of the third value after each other.
+    Encounter(u32, u32, f32),
+    /// Closed while an encounter of the map is running.
+    Door,
+    /// Enemies of the encounters of the map appear here.
+    EnemySpawn,
+    /// Finishes levels whose goal is reaching an exit.
+    Exit,
+    /// A target with the given health, levels can have destroying all of
+    /// them as goal.
+    Objective(f32),
 }
 
-struct Tile<T: Clone + Copy> {
+struct Tile {
     x: i32,
     y: i32,
-    tile_type: TileType<T>,
+    tile_type: TileType,
 }
 
-enum TileInfo<T: Clone + Copy> {
+enum TileInfo {
     StaticImage(Handle<Image>),
-    HealthImage(Handle<Image>, f32),
-    Trigger(T, f32),
-    SingleTrigger(T, f32),
+    Rock(Handle<Image>, f32, LootTable),
+    Trigger(TriggerAction, f32),
+    SingleTrigger(TriggerAction, f32),
+    Checkpoint,
+    Hazard(f32, f32),
+    WeaponPickup(WeaponKind),
+    Pickup(PickupKind),
+    Enemy(f32, f32),
+    Turret(f32),
+    Encounter(u32, u32, f32),
+    Door(Handle<Image>),
+    EnemySpawn,
+    Exit,
+    Objective(Handle<Image>, f32),
 }
 
-impl<T: Clone + Copy + Component> Tile<T> {
+impl Tile {
     pub fn spawn_tile(
         &self,
         commands: &mut Commands,
         id: Uuid,
         image_assets: &ImageAssets,
         center: Vec2,
+        merge_walls: bool,
     ) {
         let position = Vec2::new(self.x as f32 * 50.0, self.y as f32 * 50.0) + center;
         let position = Vec3::new(position.x, position.y, 0.0);
-        let tile_info = match self.tile_type {
+        let tile_info = match self.tile_type.clone() {
             TileType::Wall => TileInfo::StaticImage(image_assets.wall.clone()),
-            TileType::Rock => TileInfo::HealthImage(image_assets.rock.clone(), 10.0),
-            TileType::_Trigger(trigger, size_multiplier) => {
+            TileType::Rock(health, loot) => TileInfo::Rock(image_assets.rock.clone(), health, loot),
+            TileType::Trigger(trigger, size_multiplier) => {
                 TileInfo::Trigger(trigger, size_multiplier)
             }
             TileType::SingleTrigger(trigger, size_multiplier) => {
                 TileInfo::SingleTrigger(trigger, size_multiplier)
             }
+            TileType::Checkpoint => TileInfo::Checkpoint,
+            TileType::Hazard(damage, tick_interval) => TileInfo::Hazard(damage, tick_interval),
+            TileType::Weapon(kind) => TileInfo::WeaponPickup(kind),
+            TileType::Pickup(kind) => TileInfo::Pickup(kind),
+            TileType::Enemy(health, patrol_radius) => TileInfo::Enemy(health, patrol_radius),
+            TileType::Turret(health) => TileInfo::Turret(health),
+            TileType::Encounter(waves, enemies, delay) => {
+                TileInfo::Encounter(waves, enemies, delay)
+            }
+            TileType::Door => TileInfo::Door(image_assets.wall.clone()),
+            TileType::EnemySpawn => TileInfo::EnemySpawn,
+            TileType::Exit => TileInfo::Exit,
+            TileType::Objective(health) => TileInfo::Objective(image_assets.rock.clone(), health),
         };
         match tile_info {
             TileInfo::StaticImage(image) => {
-                commands.spawn((
+                let mut entity = commands.spawn((
                     SpriteBundle {
                         texture: image,
                         transform: Transform::from_translation(position),
@@ -65,13 +192,18 @@ impl<T: Clone + Copy + Component> Tile<T> {
                         ..default()
                     },
                     despawn::Cleanup(GameState::InGame),
-                    physics2d::PhysicsBundle::fixed_rectangle(50.0, 50.0),
                     TileMarker(id),
-                    StaticWall,
                 ));
+                // Merged walls get their collider from the map instead.
+                if !merge_walls {
+                    entity.insert((
+                        physics2d::PhysicsBundle::fixed_rectangle(50.0, 50.0),
+                        StaticWall,
+                    ));
+                }
             }
-            TileInfo::HealthImage(image, health) => {
-                commands.spawn((
+            TileInfo::Rock(image, health, loot) => {
+                let mut rock = commands.spawn((
                     SpriteBundle {
                         texture: image,
                         transform: Transform::from_translation(position),
@@ -85,7 +217,11 @@ impl<T: Clone + Copy + Component> Tile<T> {
                     physics2d::PhysicsBundle::fixed_rectangle(50.0, 50.0),
                     TileMarker(id),
                     Health::new(0.0, health),
+                    Rock,
                 ));
+                if !loot.is_empty() {
+                    rock.insert(Loot(loot));
+                }
             }
             TileInfo::Trigger(trigger, size_multiplier) => {
                 commands.spawn((
@@ -93,6 +229,8 @@ impl<T: Clone + Copy + Component> Tile<T> {
                     trigger,
                     Transform::from_translation(position),
                     GlobalTransform::default(),
+                    despawn::Cleanup(GameState::InGame),
+                    TileMarker(id),
                 ));
             }
             TileInfo::SingleTrigger(trigger, size_multiplier) => {
@@ -102,47 +240,423 @@ impl<T: Clone + Copy + Component> Tile<T> {
                     trigger,
                     Transform::from_translation(position),
                     GlobalTransform::default(),
+                    despawn::Cleanup(GameState::InGame),
+                    TileMarker(id),
+                ));
+            }
+            TileInfo::Checkpoint => {
+                commands.spawn((
+                    SpriteBundle {
+                        transform: Transform::from_translation(position),
+                        sprite: Sprite {
+                            color: Color::rgba(0.2, 0.9, 0.4, 0.3),
+                            custom_size: Some(Vec2::new(50.0, 50.0)),
+                            ..default()
+                        },
+                        ..default()
+                    },
+                    physics2d::PhysicsBundle::trigger(50.0, 50.0, 1.0),
+                    Checkpoint,
+                    despawn::Cleanup(GameState::InGame),
+                    TileMarker(id),
+                ));
+            }
+            TileInfo::Hazard(damage, tick_interval) => {
+                commands.spawn((
+                    SpriteBundle {
+                        transform: Transform::from_translation(position),
+                        sprite: Sprite {
+                            color: Color::rgba(0.9, 0.3, 0.1, 0.5),
+                            custom_size: Some(Vec2::new(50.0, 50.0)),
+                            ..default()
+                        },
+                        ..default()
+                    },
+                    // A bit smaller, so it doesn't touch neighbouring tiles.
+                    physics2d::PhysicsBundle::trigger(50.0, 50.0, 0.9),
+                    Damager::new_persistent(damage, tick_interval),
+                    despawn::Cleanup(GameState::InGame),
+                    TileMarker(id),
+                ));
+            }
+            TileInfo::WeaponPickup(kind) => {
+                commands.spawn((
+                    SpriteBundle {
+                        texture: image_assets.bullet.clone(),
+                        transform: Transform::from_translation(position),
+                        sprite: Sprite {
+                            custom_size: Some(Vec2::new(30.0, 30.0)),
+                            ..kind.weapon().projectile_look
+                        },
+                        ..default()
+                    },
+                    physics2d::PhysicsBundle::trigger(50.0, 50.0, 0.6),
+                    WeaponPickup(kind),
+                    despawn::Cleanup(GameState::InGame),
+                    TileMarker(id),
+                ));
+            }
+            TileInfo::Pickup(kind) => {
+                let pickup = pickup::spawn_pickup(commands, image_assets, position.xy(), kind);
+                commands.entity(pickup).insert(TileMarker(id));
+            }
+            TileInfo::Enemy(health, patrol_radius) => {
+                let enemy = enemy::spawn_enemy(
+                    commands,
+                    image_assets,
+                    position.xy(),
+                    health,
+                    patrol_radius,
+                );
+                commands
+                    .entity(enemy)
+                    .insert((despawn::Cleanup(GameState::InGame), TileMarker(id)));
+            }
+            TileInfo::Turret(health) => {
+                let turret = turret::spawn_turret(commands, image_assets, position.xy(), health);
+                commands
+                    .entity(turret)
+                    .insert((despawn::Cleanup(GameState::InGame), TileMarker(id)));
+            }
+            TileInfo::Encounter(waves, enemies, delay) => {
+                commands.spawn((
+                    physics2d::PhysicsBundle::trigger(50.0, 50.0, 1.0),
+                    EncounterTrigger {
+                        waves: (0..waves)
+                            .map(|_| Wave {
+                                enemies,
+                                enemy_health: DEFAULT_ENEMY_HEALTH,
+                                delay,
+                            })
+                            .collect(),
+                    },
+                    Transform::from_translation(position),
+                    GlobalTransform::default(),
+                    despawn::Cleanup(GameState::InGame),
+                    TileMarker(id),
+                ));
+            }
+            TileInfo::Door(image) => {
+                // Doors stay open until an encounter starts.
+                commands.spawn((
+                    SpriteBundle {
+                        texture: image,
+                        transform: Transform::from_translation(position),
+                        sprite: Sprite {
+                            color: Color::rgb(1.0, 0.6, 0.6),
+                            custom_size: Some(Vec2::new(50.0, 50.0)),
+                            ..default()
+                        },
+                        visibility: Visibility::Hidden,
+                        ..default()
+                    },
+                    physics2d::PhysicsBundle::fixed_rectangle(50.0, 50.0),
+                    ColliderDisabled,
+                    StaticWall,
+                    Door,
+                    despawn::Cleanup(GameState::InGame),
+                    TileMarker(id),
+                ));
+            }
+            TileInfo::EnemySpawn => {
+                commands.spawn((
+                    TransformBundle::from_transform(Transform::from_translation(position)),
+                    EnemySpawnPoint,
+                    despawn::Cleanup(GameState::InGame),
+                    TileMarker(id),
+                ));
+            }
+            TileInfo::Exit => {
+                commands.spawn((
+                    SpriteBundle {
+                        transform: Transform::from_translation(position),
+                        sprite: Sprite {
+                            color: Color::rgba(0.3, 0.6, 1.0, 0.5),
+                            custom_size: Some(Vec2::new(50.0, 50.0)),
+                            ..default()
+                        },
+                        ..default()
+                    },
+                    physics2d::PhysicsBundle::trigger(50.0, 50.0, 1.0),
+                    Exit,
+                    despawn::Cleanup(GameState::InGame),
+                    TileMarker(id),
+                ));
+            }
+            TileInfo::Objective(image, health) => {
+                commands.spawn((
+                    SpriteBundle {
+                        texture: image,
+                        transform: Transform::from_translation(position),
+                        sprite: Sprite {
+                            color: Color::rgb(1.0, 0.8, 0.2),
+                            custom_size: Some(Vec2::new(50.0, 50.0)),
+                            ..default()
+                        },
+                        ..default()
+                    },
+                    physics2d::PhysicsBundle::fixed_rectangle(50.0, 50.0),
+                    Health::new(0.0, health),
+                    Objective,
+                    despawn::Cleanup(GameState::InGame),
+                    TileMarker(id),
                 ));
             }
         }
     }
 }
 
-pub struct Map<T: Clone + Copy> {
-    tiles: Vec<Tile<T>>,
+/// A rectangle of wall tiles in tile coordinates, `x` and `y` being the
+/// bottom left tile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WallRect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+pub struct Map {
+    tiles: Vec<Tile>,
     pub id: Uuid,
+    pub source: Option<AssetId<MapAsset>>,
+
+    /// Spawn one collider per rectangle of adjacent walls instead of one
+    /// per wall tile.
+    pub merge_walls: bool,
+}
+
+impl Default for Map {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl<T: Clone + Copy + Component> Map<T> {
+impl Map {
     pub fn new() -> Self {
         Self {
             tiles: Vec::new(),
             id: Uuid::new_v4(),
+            source: None,
+            merge_walls: false,
         }
     }
 
+    pub fn with_merged_walls(mut self, merge_walls: bool) -> Self {
+        self.merge_walls = merge_walls;
+        self
+    }
+
+    pub fn with_source(mut self, source: impl Into<AssetId<MapAsset>>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
     pub fn spawn_tiles(&self, commands: &mut Commands, image_assets: &ImageAssets, center: Vec2) {
+        commands.spawn((
+            SpawnedMap {
+                id: self.id,
+                source: self.source,
+                center,
+            },
+            despawn::Cleanup(GameState::InGame),
+            TileMarker(self.id),
+        ));
+        for tile in &self.tiles {
+            tile.spawn_tile(commands, self.id, image_assets, center, self.merge_walls);
+        }
+        if self.merge_walls {
+            for wall_rect in self.wall_rects() {
+                let position = Vec2::new(
+                    (wall_rect.x as f32 + (wall_rect.width - 1) as f32 / 2.0) * 50.0,
+                    (wall_rect.y as f32 + (wall_rect.height - 1) as f32 / 2.0) * 50.0,
+                ) + center;
+                commands.spawn((
+                    physics2d::PhysicsBundle::fixed_rectangle(
+                        wall_rect.width as f32 * 50.0,
+                        wall_rect.height as f32 * 50.0,
+                    ),
+                    Transform::from_translation(position.extend(0.0)),
+                    GlobalTransform::default(),
+                    despawn::Cleanup(GameState::InGame),
+                    TileMarker(self.id),
+                    StaticWall,
+                ));
+            }
+        }
+    }
+
+    /// Draws the tiles as text, top row first.
+    ///
+    /// The center of the map, where the map is placed in the world, is shown
+    /// as `@` if it's empty and as `!` if something covers it.
+    pub fn render_ascii(&self) -> String {
+        let min_x = self
+            .tiles
+            .iter()
+            .map(|tile| tile.x)
+            .min()
+            .unwrap_or(0)
+            .min(0);
+        let max_x = self
+            .tiles
+            .iter()
+            .map(|tile| tile.x)
+            .max()
+            .unwrap_or(0)
+            .max(0);
+        let min_y = self
+            .tiles
+            .iter()
+            .map(|tile| tile.y)
+            .min()
+            .unwrap_or(0)
+            .min(0);
+        let max_y = self
+            .tiles
+            .iter()
+            .map(|tile| tile.y)
+            .max()
+            .unwrap_or(0)
+            .max(0);
+        let width = (max_x - min_x + 1) as usize;
+        let height = (max_y - min_y + 1) as usize;
+        let mut grid = vec![vec!['.'; width]; height];
         for tile in &self.tiles {
-            tile.spawn_tile(commands, self.id, image_assets, center);
+            grid[(max_y - tile.y) as usize][(tile.x - min_x) as usize] = match tile.tile_type {
+                TileType::Wall => '#',
+                TileType::Rock(..) => 'O',
+                TileType::Trigger(..) => 'T',
+                TileType::SingleTrigger(..) => 't',
+                TileType::Checkpoint => 'C',
+                TileType::Hazard(..) => '~',
+                TileType::Weapon(_) => 'W',
+                TileType::Pickup(_) => 'P',
+                TileType::Enemy(..) => 'E',
+                TileType::Turret(_) => 'Y',
+                TileType::Encounter(..) => 'A',
+                TileType::Door => 'D',
+                TileType::EnemySpawn => 's',
+                TileType::Exit => '>',
+                TileType::Objective(_) => '*',
+            };
+        }
+        let center = &mut grid[max_y as usize][(-min_x) as usize];
+        *center = if *center == '.' { '@' } else { '!' };
+
+        let mut ascii = format!("x: {}..={}, y: {}..={}\n", min_x, max_x, min_y, max_y);
+        for row in grid {
+            ascii.extend(row);
+            ascii.push('\n');
         }
+        ascii
+    }
+
+    /// Greedily covers all wall tiles with as few rectangles as possible.
+    ///
+    /// Starting at the bottom left, each rectangle grows to the right as far
+    /// as possible and then upwards as long as the whole row is free walls.
+    pub fn wall_rects(&self) -> Vec<WallRect> {
+        let walls: HashSet<(i32, i32)> = self
+            .tiles
+            .iter()
+            .filter(|tile| matches!(tile.tile_type, TileType::Wall))
+            .map(|tile| (tile.x, tile.y))
+            .collect();
+        let mut sorted_walls: Vec<(i32, i32)> = walls.iter().copied().collect();
+        sorted_walls.sort_by_key(|(x, y)| (*y, *x));
+
+        let mut covered = HashSet::new();
+        let mut wall_rects = Vec::new();
+        let is_free = |covered: &HashSet<(i32, i32)>, x: i32, y: i32| {
+            walls.contains(&(x, y)) && !covered.contains(&(x, y))
+        };
+        for (x, y) in sorted_walls {
+            if covered.contains(&(x, y)) {
+                continue;
+            }
+            let mut width = 1;
+            while is_free(&covered, x + width, y) {
+                width += 1;
+            }
+            let mut height = 1;
+            while (x..x + width).all(|column| is_free(&covered, column, y + height)) {
+                height += 1;
+            }
+            for row in y..y + height {
+                for column in x..x + width {
+                    covered.insert((column, row));
+                }
+            }
+            wall_rects.push(WallRect {
+                x,
+                y,
+                width,
+                height,
+            });
+        }
+        wall_rects
     }
 }
 
 #[derive(Error, Debug)]
 pub enum MapDraftError {
-    #[error("str width is inconsistent. Every &str of a [&str] must have the same length")]
-    InconsistentWidth,
+    #[error("line {line} is {found} tiles wide, but every line must be {expected} tiles wide")]
+    InconsistentWidth {
+        line: usize,
+        expected: u32,
+        found: u32,
+    },
 
     #[error("str length does not match, must be width times height long")]
     StrLengthMismatch,
+
+    #[error("invalid tile definition: {0}")]
+    InvalidTileDefinition(String),
+
+    #[error("unknown weapon: {0}")]
+    UnknownWeapon(String),
+
+    #[error("unknown pickup: {0}")]
+    UnknownPickup(String),
+
+    #[error("map has no tiles")]
+    EmptyMap,
+
+    #[error("line {line}, column {column}: '{tile}' is not in the legend")]
+    UnknownTile {
+        line: usize,
+        column: usize,
+        tile: char,
+    },
+
+    #[error("center ({x}, {y}) is outside of the {width}x{height} map")]
+    CenterOutOfBounds {
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+    },
+
+    #[error(
+        "line {line}, column {column}: trigger, checkpoint or pickup can't be reached from the center"
+    )]
+    UnreachableTrigger { line: usize, column: usize },
+
+    #[error("line {line}, column {column}: no script for trigger `{action}`")]
+    MissingScript {
+        line: usize,
+        column: usize,
+        action: TriggerAction,
+    },
 }
 
-pub struct MapDraft<T: Clone + Copy> {
+pub struct MapDraft {
     pub width: u32,
-    pub tiles: Vec<Option<TileType<T>>>,
+    pub tiles: Vec<Option<TileType>>,
 }
 
-impl<T: Clone + Copy + Component> MapDraft<T> {
+impl MapDraft {
     pub fn new(width: u32, height: u32) -> Self {
         Self {
             width,
@@ -158,16 +672,16 @@ impl<T: Clone + Copy + Component> MapDraft<T> {
         (index as u32 % self.width, index as u32 / self.width)
     }
 
-    pub fn _get_tile(&self, x: u32, y: u32) -> Option<TileType<T>> {
-        self.tiles[self.pos_to_index(x, y)]
+    pub fn _get_tile(&self, x: u32, y: u32) -> Option<TileType> {
+        self.tiles[self.pos_to_index(x, y)].clone()
     }
 
-    pub fn set_tile(&mut self, x: u32, y: u32, tile_type: TileType<T>) {
+    pub fn set_tile(&mut self, x: u32, y: u32, tile_type: TileType) {
         let index = self.pos_to_index(x, y);
         self.tiles[index] = Some(tile_type);
     }
 
-    pub fn to_map(&self, center: (i32, i32)) -> Map<T> {
+    pub fn to_map(&self, center: (i32, i32)) -> Map {
         let mut map = Map::new();
         for (index, tile) in self.tiles.iter().enumerate() {
             if let Some(tile) = tile {
@@ -175,7 +689,7 @@ impl<T: Clone + Copy + Component> MapDraft<T> {
                 map.tiles.push(Tile {
                     x: x as i32 - center.0,
                     y: y as i32 - center.1,
-                    tile_type: *tile,
+                    tile_type: tile.clone(),
                 });
             }
         }
@@ -186,9 +700,12 @@ impl<T: Clone + Copy + Component> MapDraft<T> {
         map_str: &str,
         width: u32,
         height: u32,
-        tile_mapper: Box<dyn Fn(char) -> Option<TileType<T>>>,
-    ) -> Result<MapDraft<T>, MapDraftError> {
-        if map_str.len() as u32 != width * height {
+        tile_mapper: Box<dyn Fn(char) -> Option<TileType>>,
+    ) -> Result<MapDraft, MapDraftError> {
+        if width == 0 || height == 0 {
+            return Err(MapDraftError::EmptyMap);
+        }
+        if map_str.chars().count() as u32 != width * height {
             return Err(MapDraftError::StrLengthMismatch);
         }
         let mut draft = MapDraft::new(width, height);
@@ -204,14 +721,26 @@ impl<T: Clone + Copy + Component> MapDraft<T> {
 
     pub fn from_str_array(
         array: &[&str],
-        tile_mapper: Box<dyn Fn(char) -> Option<TileType<T>>>,
-    ) -> Result<MapDraft<T>, MapDraftError> {
-        let width = array[0].len() as u32;
+        tile_mapper: Box<dyn Fn(char) -> Option<TileType>>,
+    ) -> Result<MapDraft, MapDraftError> {
+        let width = array
+            .first()
+            .ok_or(MapDraftError::EmptyMap)?
+            .chars()
+            .count() as u32;
         let height = array.len() as u32;
+        if width == 0 {
+            return Err(MapDraftError::EmptyMap);
+        }
         let mut draft = MapDraft::new(width, height);
         for (y, line) in array.iter().rev().enumerate() {
-            if line.len() as u32 != width {
-                return Err(MapDraftError::InconsistentWidth);
+            let line_width = line.chars().count() as u32;
+            if line_width != width {
+                return Err(MapDraftError::InconsistentWidth {
+                    line: height as usize - y,
+                    expected: width,
+                    found: line_width,
+                });
             }
             for (x, c) in line.chars().enumerate() {
                 if let Some(tile) = tile_mapper(c) {
@@ -222,3 +751,140 @@ impl<T: Clone + Copy + Component> MapDraft<T> {
         Ok(draft)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_asset::read_map_asset;
+    use crate::test_support::TestGame;
+    use bevy::ecs::system::CommandQueue;
+    use bevy_rapier2d::prelude::Collider;
+
+    fn count_wall_colliders(map: &Map) -> usize {
+        let mut world = World::new();
+        let mut queue = CommandQueue::default();
+        let mut commands = Commands::new(&mut queue, &world);
+        map.spawn_tiles(&mut commands, &ImageAssets::default(), Vec2::ZERO);
+        queue.apply(&mut world);
+        world
+            .query_filtered::<(), (With<Collider>, With<StaticWall>, Without<Door>)>()
+            .iter(&world)
+            .count()
+    }
+
+    fn assert_fewer_colliders_when_merged(map: Map) {
+        let wall_count = map
+            .tiles
+            .iter()
+            .filter(|tile| matches!(tile.tile_type, TileType::Wall))
+            .count();
+        let wall_rects = map.wall_rects();
+        let covered_area: i32 = wall_rects
+            .iter()
+            .map(|wall_rect| wall_rect.width * wall_rect.height)
+            .sum();
+        assert_eq!(covered_area as usize, wall_count);
+
+        let map = map.with_merged_walls(false);
+        assert_eq!(count_wall_colliders(&map), wall_count);
+        let map = map.with_merged_walls(true);
+        let merged_colliders = count_wall_colliders(&map);
+        assert_eq!(merged_colliders, wall_rects.len());
+        assert!(merged_colliders < wall_count);
+    }
+
+    fn entities_of_map<F: bevy::ecs::query::QueryFilter>(game: &mut TestGame, id: Uuid) -> usize {
+        game.app
+            .world
+            .query_filtered::<&TileMarker, F>()
+            .iter(&game.app.world)
+            .filter(|marker| marker.0 == id)
+            .count()
+    }
+
+    #[test]
+    fn unload_only_the_given_map() {
+        let mut game = TestGame::new();
+        let tiles = |c| match c {
+            'X' => Some(TileType::Wall),
+            'T' => Some(TileType::SingleTrigger(
+                TriggerAction("Unloaded".to_string()),
+                1.0,
+            )),
+            _ => None,
+        };
+        let unloaded = game.spawn_map("XT", tiles);
+        let kept = game.spawn_map("XT", tiles);
+        assert_eq!(
+            entities_of_map::<With<TriggerAction>>(&mut game, unloaded),
+            1
+        );
+
+        game.with_commands(|commands, _| commands.unload_map(unloaded));
+        game.step(1);
+        assert_eq!(entities_of_map::<()>(&mut game, unloaded), 0);
+        assert_eq!(
+            entities_of_map::<With<TriggerAction>>(&mut game, unloaded),
+            0
+        );
+        assert!(!game
+            .app
+            .world
+            .query::<&SpawnedMap>()
+            .iter(&game.app.world)
+            .any(|spawned| spawned.id == unloaded));
+
+        assert_eq!(entities_of_map::<With<SpawnedMap>>(&mut game, kept), 1);
+        assert_eq!(entities_of_map::<With<TriggerAction>>(&mut game, kept), 1);
+        assert!(entities_of_map::<With<Collider>>(&mut game, kept) > 0);
+    }
+
+    #[test]
+    fn merge_walls_of_tutorial() {
+        assert_fewer_colliders_when_merged(read_map_asset("tutorial").build_map().unwrap());
+    }
+
+    #[test]
+    fn merge_walls_of_level_1() {
+        assert_fewer_colliders_when_merged(read_map_asset("level_1").build_map().unwrap());
+    }
+
+    #[test]
+    fn render_ascii_marks_center() {
+        let draft = MapDraft::from_str(
+            "X.OX",
+            2,
+            2,
+            Box::new(|c| match c {
+                'X' => Some(TileType::Wall),
+                'O' => Some(TileType::Rock(10.0, LootTable::default())),
+                _ => None,
+            }),
+        )
+        .unwrap();
+        assert_eq!(
+            draft.to_map((1, 1)).render_ascii(),
+            "x: -1..=0, y: -1..=0\n#@\nO#\n"
+        );
+    }
+
+    #[test]
+    fn merge_walls_into_single_rectangle() {
+        let draft = MapDraft::from_str(
+            "XXXXXX",
+            3,
+            2,
+            Box::new(|c| (c == 'X').then_some(TileType::Wall)),
+        )
+        .unwrap();
+        assert_eq!(
+            draft.to_map((0, 0)).wall_rects(),
+            vec![WallRect {
+                x: 0,
+                y: 0,
+                width: 3,
+                height: 2
+            }]
+        );
+    }
+}
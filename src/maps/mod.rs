@@ -1,2 +1,153 @@
-pub mod level_1;
-pub mod tutorial;
+use std::fmt;
+use std::str::FromStr;
+
+use bevy::prelude::*;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{assets, error_handler::GameError, map_asset::MapAsset, map_builder};
+
+/// The levels of the game in the order they're played.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug, Default)]
+pub enum Level {
+    #[default]
+    Tutorial,
+    Level1,
+}
+
+impl Level {
+    pub const ALL: [Level; 2] = [Level::Tutorial, Level::Level1];
+
+    /// The entry of the level in [`LEVELS`].
+    pub fn info(self) -> &'static LevelInfo {
+        &LEVELS[self as usize]
+    }
+}
+
+#[derive(Error, Clone, PartialEq, Debug)]
+#[error("unknown level `{0}`")]
+pub struct UnknownLevel(pub String);
+
+impl FromStr for Level {
+    type Err = UnknownLevel;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tutorial" => Ok(Level::Tutorial),
+            "level_1" => Ok(Level::Level1),
+            _ => Err(UnknownLevel(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Tutorial => write!(f, "tutorial"),
+            Level::Level1 => write!(f, "level_1"),
+        }
+    }
+}
+
+/// What the player has to do to finish a level.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LevelExit {
+    /// A script of the map finishes the level with `complete_level`.
+    Script,
+
+    /// The player has to fly into an exit tile once every key of the map is
+    /// collected.
+    ReachExit,
+
+    /// Every rock of the map has to be shot.
+    DestroyRocks,
+
+    /// Every enemy and turret of the map has to be destroyed and every
+    /// encounter beaten.
+    DestroyEnemies,
+
+    /// Every objective tile of the map has to be destroyed.
+    DestroyObjectives,
+
+    /// The player has to stay alive for the given seconds.
+    Survive(f32),
+}
+
+pub struct LevelInfo {
+    pub level: Level,
+
+    /// Shown to the player, like on the level select screen.
+    pub name: &'static str,
+    pub map: fn(&assets::MapAssets) -> &Handle<MapAsset>,
+    pub exit: LevelExit,
+
+    /// The level the player flies to afterwards, the run ends after the last
+    /// level.
+    pub next: Option<Level>,
+}
+
+/// Every level in the order of [`Level`].
+pub const LEVELS: [LevelInfo; 2] = [
+    LevelInfo {
+        level: Level::Tutorial,
+        name: "Tutorial",
+        map: |map_assets| &map_assets.tutorial,
+        exit: LevelExit::Script,
+        next: Some(Level::Level1),
+    },
+    LevelInfo {
+        level: Level::Level1,
+        name: "Level 1",
+        map: |map_assets| &map_assets.level_1,
+        exit: LevelExit::ReachExit,
+        next: None,
+    },
+];
+
+/// The player made it through the level.
+#[derive(Event, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct LevelComplete(pub Level);
+
+pub fn build_level(
+    level: Level,
+    map_assets: &assets::MapAssets,
+    maps: &Assets<MapAsset>,
+) -> Result<map_builder::Map, GameError> {
+    let info = level.info();
+    let handle = (info.map)(map_assets);
+    let map = maps.get(handle).ok_or(GameError::MapNotLoaded(info.name))?;
+    Ok(map.build_validated_map()?.with_source(handle))
+}
+
+/// Spawns the map of the level centered at the given position and returns its
+/// id.
+pub fn spawn_level(
+    commands: &mut Commands,
+    level: Level,
+    map_assets: &assets::MapAssets,
+    loaded_maps: &Assets<MapAsset>,
+    image_assets: &assets::ImageAssets,
+    center: Vec2,
+) -> Result<Uuid, GameError> {
+    let map = build_level(level, map_assets, loaded_maps)?;
+    map.spawn_tiles(commands, image_assets, center);
+    Ok(map.id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_are_registered_in_order() {
+        for level in Level::ALL {
+            assert_eq!(level.info().level, level);
+            assert_eq!(level.to_string().parse(), Ok(level));
+        }
+        // Every level but the last leads to the one after it.
+        for pair in Level::ALL.windows(2) {
+            assert_eq!(pair[0].info().next, Some(pair[1]));
+        }
+        assert_eq!(Level::ALL.last().unwrap().info().next, None);
+    }
+}
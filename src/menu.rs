@@ -0,0 +1,808 @@
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use some_bevy_tools::despawn;
+
+use crate::{
+    goal::LevelStats,
+    keymap::{BindableAction, Binding, Keymap, KeymapStorage},
+    maps::Level,
+    progression::StartLevel,
+    save::{self, SaveGame, SaveStorage},
+    GameState, InGameState,
+};
+
+/// Pausing is its own state, so leaving the game for the pause menu doesn't
+/// tear the level down like leaving `GameState::InGame` would.
+#[derive(States, PartialEq, Eq, Debug, Default, Hash, Clone, Copy)]
+pub enum PauseState {
+    #[default]
+    Running,
+    Paused,
+
+    /// A level was finished and its results are shown.
+    Results,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MenuAction {
+    Start,
+    LevelSelect,
+    PlayLevel(Level),
+    Resume,
+    Continue,
+    MainMenu,
+    Retry,
+    Controls,
+    Rebind(BindableAction),
+    ResetControls,
+    ToggleSkipTutorial,
+    #[cfg(not(target_arch = "wasm32"))]
+    Quit,
+}
+impl MenuAction {
+    pub fn label(&self) -> &'static str {
+        match self {
+            MenuAction::Start => "Start",
+            MenuAction::LevelSelect => "Level Select",
+            MenuAction::PlayLevel(level) => level.info().name,
+            MenuAction::Resume => "Resume",
+            MenuAction::Continue => "Continue",
+            MenuAction::MainMenu => "Main Menu",
+            MenuAction::Retry => "Retry",
+            MenuAction::Controls => "Controls",
+            MenuAction::Rebind(action) => action.label(),
+            MenuAction::ResetControls => "Reset Controls",
+            MenuAction::ToggleSkipTutorial => "Skip Tutorial",
+            #[cfg(not(target_arch = "wasm32"))]
+            MenuAction::Quit => "Quit",
+        }
+    }
+}
+
+#[derive(Component)]
+pub struct MenuButton {
+    pub index: usize,
+    pub action: MenuAction,
+}
+
+/// Index of the highlighted button of the open menu.
+#[derive(Resource, Default)]
+pub struct MenuSelection(pub usize);
+
+#[derive(Event)]
+pub struct MenuActionEvent(pub MenuAction);
+
+/// State of the controls screen.
+#[derive(Resource, Default)]
+pub struct Rebinding {
+    /// The action which gets the next pressed key or gamepad button.
+    pub action: Option<BindableAction>,
+
+    /// Tells the player what happened to the last change.
+    pub message: String,
+}
+
+/// Text of the controls screen which shows [`Rebinding::message`].
+#[derive(Component)]
+pub struct ControlsMessage;
+
+const BUTTON_COLOR: Color = Color::rgb(0.15, 0.15, 0.25);
+const SELECTED_BUTTON_COLOR: Color = Color::rgb(0.35, 0.35, 0.6);
+
+/// Spawns a title and a button per action, returns the root node.
+fn spawn_menu(
+    commands: &mut Commands,
+    selection: &mut MenuSelection,
+    title: &str,
+    actions: &[MenuAction],
+    cleanup: impl Bundle,
+) -> Entity {
+    selection.0 = 0;
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    width: Val::Percent(100.0),
+                    height: Val::Percent(100.0),
+                    flex_direction: FlexDirection::Column,
+                    align_items: AlignItems::Center,
+                    justify_content: JustifyContent::Center,
+                    row_gap: Val::Px(20.0),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.05, 0.8).into(),
+                ..default()
+            },
+            cleanup,
+        ))
+        .with_children(|parent| {
+            parent.spawn(TextBundle::from_section(
+                title,
+                TextStyle {
+                    font_size: 60.0,
+                    color: Color::WHITE,
+                    ..default()
+                },
+            ));
+            for (index, action) in actions.iter().enumerate() {
+                parent
+                    .spawn((
+                        ButtonBundle {
+                            style: Style {
+                                min_width: Val::Px(250.0),
+                                height: Val::Px(60.0),
+                                padding: UiRect::horizontal(Val::Px(20.0)),
+                                align_items: AlignItems::Center,
+                                justify_content: JustifyContent::Center,
+                                ..default()
+                            },
+                            background_color: BUTTON_COLOR.into(),
+                            ..default()
+                        },
+                        MenuButton {
+                            index,
+                            action: *action,
+                        },
+                    ))
+                    .with_children(|parent| {
+                        parent.spawn(TextBundle::from_section(
+                            action.label(),
+                            TextStyle {
+                                font_size: 30.0,
+                                color: Color::WHITE,
+                                ..default()
+                            },
+                        ));
+                    });
+            }
+        })
+        .id()
+}
+
+pub fn spawn_main_menu(
+    mut commands: Commands,
+    mut selection: ResMut<MenuSelection>,
+    save_game: Res<SaveGame>,
+) {
+    commands.spawn((
+        Camera2dBundle::default(),
+        despawn::Cleanup(GameState::MainMenu),
+    ));
+    let mut actions = vec![MenuAction::Start];
+    if !save_game.completed.is_empty() {
+        actions.push(MenuAction::LevelSelect);
+    }
+    // Skipping is only offered once the tutorial was played through.
+    if save_game.completed.contains(&Level::Tutorial) {
+        actions.push(MenuAction::ToggleSkipTutorial);
+    }
+    actions.extend([
+        MenuAction::Controls,
+        #[cfg(not(target_arch = "wasm32"))]
+        MenuAction::Quit,
+    ]);
+    spawn_menu(
+        &mut commands,
+        &mut selection,
+        "Some Bevy Game",
+        &actions,
+        despawn::Cleanup(GameState::MainMenu),
+    );
+}
+
+pub fn spawn_controls_menu(
+    mut commands: Commands,
+    mut selection: ResMut<MenuSelection>,
+    mut rebinding: ResMut<Rebinding>,
+) {
+    *rebinding = Rebinding::default();
+    commands.spawn((
+        Camera2dBundle::default(),
+        despawn::Cleanup(GameState::Controls),
+    ));
+    let actions = BindableAction::ALL
+        .into_iter()
+        .map(MenuAction::Rebind)
+        .chain([MenuAction::ResetControls, MenuAction::MainMenu])
+        .collect::<Vec<_>>();
+    spawn_menu(
+        &mut commands,
+        &mut selection,
+        "Controls",
+        &actions,
+        despawn::Cleanup(GameState::Controls),
+    );
+    commands.spawn((
+        TextBundle::from_section(
+            "",
+            TextStyle {
+                font_size: 24.0,
+                color: Color::WHITE,
+                ..default()
+            },
+        )
+        .with_style(Style {
+            position_type: PositionType::Absolute,
+            bottom: Val::Px(20.0),
+            left: Val::Px(20.0),
+            ..default()
+        }),
+        ControlsMessage,
+        despawn::Cleanup(GameState::Controls),
+    ));
+}
+
+/// Lists the completed levels to play them again.
+pub fn spawn_level_select(
+    mut commands: Commands,
+    mut selection: ResMut<MenuSelection>,
+    save_game: Res<SaveGame>,
+) {
+    commands.spawn((
+        Camera2dBundle::default(),
+        despawn::Cleanup(GameState::LevelSelect),
+    ));
+    let actions = Level::ALL
+        .into_iter()
+        .filter(|level| save_game.completed.contains(level))
+        .map(MenuAction::PlayLevel)
+        .chain([MenuAction::MainMenu])
+        .collect::<Vec<_>>();
+    spawn_menu(
+        &mut commands,
+        &mut selection,
+        "Level Select",
+        &actions,
+        despawn::Cleanup(GameState::LevelSelect),
+    );
+}
+
+pub fn spawn_pause_menu(mut commands: Commands, mut selection: ResMut<MenuSelection>) {
+    spawn_menu(
+        &mut commands,
+        &mut selection,
+        "Paused",
+        &[MenuAction::Resume, MenuAction::MainMenu],
+        despawn::Cleanup(PauseState::Paused),
+    );
+}
+
+/// Shows how the finished level went before the game goes on.
+pub fn spawn_results_menu(
+    mut commands: Commands,
+    mut selection: ResMut<MenuSelection>,
+    stats: Res<LevelStats>,
+    in_game_state: Res<InGameState>,
+) {
+    let menu = spawn_menu(
+        &mut commands,
+        &mut selection,
+        "Level Complete",
+        &[MenuAction::Continue, MenuAction::MainMenu],
+        despawn::Cleanup(PauseState::Results),
+    );
+    let seconds = stats.time as u32;
+    let results = commands
+        .spawn(TextBundle::from_section(
+            format!(
+                "{}\nTime: {}:{:02}\nEnemies destroyed: {}\nRocks destroyed: {}\nScore: {}\nLives left: {}",
+                stats.level.info().name,
+                seconds / 60,
+                seconds % 60,
+                stats.enemies_destroyed,
+                stats.rocks_destroyed,
+                in_game_state.score,
+                in_game_state.lives,
+            ),
+            TextStyle {
+                font_size: 30.0,
+                color: Color::WHITE,
+                ..default()
+            },
+        ))
+        .id();
+    // Between the title and the buttons.
+    commands.entity(menu).insert_children(1, &[results]);
+}
+
+pub fn spawn_game_over(mut commands: Commands, mut selection: ResMut<MenuSelection>) {
+    commands.spawn((
+        Camera2dBundle::default(),
+        despawn::Cleanup(GameState::GameOver),
+    ));
+    spawn_menu(
+        &mut commands,
+        &mut selection,
+        "Game Over",
+        &[MenuAction::Retry, MenuAction::MainMenu],
+        despawn::Cleanup(GameState::GameOver),
+    );
+}
+
+/// Moves the selection with the keyboard, the gamepad or the mouse and
+/// sends the action of the selected button on confirmation.
+pub fn menu_navigation(
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    buttons: Query<&MenuButton>,
+    interactions: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    mut selection: ResMut<MenuSelection>,
+    mut menu_actions: EventWriter<MenuActionEvent>,
+) {
+    let gamepad_just_pressed = |button_type| {
+        gamepads
+            .iter()
+            .any(|gamepad| gamepad_buttons.just_pressed(GamepadButton::new(gamepad, button_type)))
+    };
+    let button_count = buttons.iter().count();
+    if button_count == 0 {
+        return;
+    }
+    if keys.any_just_pressed([KeyCode::ArrowUp, KeyCode::KeyW])
+        || gamepad_just_pressed(GamepadButtonType::DPadUp)
+    {
+        selection.0 = (selection.0 + button_count - 1) % button_count;
+    }
+    if keys.any_just_pressed([KeyCode::ArrowDown, KeyCode::KeyS])
+        || gamepad_just_pressed(GamepadButtonType::DPadDown)
+    {
+        selection.0 = (selection.0 + 1) % button_count;
+    }
+    let mut confirmed = keys.any_just_pressed([KeyCode::Enter, KeyCode::Space])
+        || gamepad_just_pressed(GamepadButtonType::South);
+    for (interaction, button) in interactions.iter() {
+        match interaction {
+            Interaction::Hovered => selection.0 = button.index,
+            Interaction::Pressed => {
+                selection.0 = button.index;
+                confirmed = true;
+            }
+            Interaction::None => {}
+        }
+    }
+    if confirmed {
+        if let Some(button) = buttons.iter().find(|button| button.index == selection.0) {
+            menu_actions.send(MenuActionEvent(button.action));
+        }
+    }
+}
+
+pub fn highlight_selection(
+    selection: Res<MenuSelection>,
+    mut buttons: Query<(&MenuButton, &mut BackgroundColor)>,
+) {
+    for (button, mut color) in buttons.iter_mut() {
+        *color = if button.index == selection.0 {
+            SELECTED_BUTTON_COLOR.into()
+        } else {
+            BUTTON_COLOR.into()
+        };
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn menu_action_handler(
+    mut menu_actions: EventReader<MenuActionEvent>,
+    mut game_state: ResMut<NextState<GameState>>,
+    mut pause_state: ResMut<NextState<PauseState>>,
+    mut rebinding: ResMut<Rebinding>,
+    mut keymap: ResMut<Keymap>,
+    mut storage: ResMut<KeymapStorage>,
+    mut save_game: ResMut<SaveGame>,
+    mut save_storage: ResMut<SaveStorage>,
+    mut start_level: ResMut<StartLevel>,
+    #[cfg(not(target_arch = "wasm32"))] mut app_exit: EventWriter<AppExit>,
+) {
+    for MenuActionEvent(action) in menu_actions.read() {
+        match action {
+            MenuAction::Start => {
+                start_level.0 = None;
+                game_state.set(GameState::InGame);
+            }
+            MenuAction::LevelSelect => game_state.set(GameState::LevelSelect),
+            MenuAction::PlayLevel(level) => {
+                start_level.0 = Some(*level);
+                game_state.set(GameState::InGame);
+            }
+            // Retrying starts the same level again if one was picked.
+            MenuAction::Retry => game_state.set(GameState::InGame),
+            MenuAction::Resume | MenuAction::Continue => pause_state.set(PauseState::Running),
+            MenuAction::MainMenu => {
+                pause_state.set(PauseState::Running);
+                game_state.set(GameState::MainMenu);
+            }
+            MenuAction::Controls => game_state.set(GameState::Controls),
+            MenuAction::Rebind(action) => {
+                rebinding.action = Some(*action);
+                rebinding.message = format!(
+                    "Press a key or gamepad button for {}, escape cancels",
+                    action.label()
+                );
+            }
+            MenuAction::ResetControls => {
+                *keymap = Keymap::default();
+                save_keymap(&keymap, &mut storage);
+                rebinding.message = "Controls reset".to_string();
+            }
+            MenuAction::ToggleSkipTutorial => {
+                save_game.settings.skip_tutorial = !save_game.settings.skip_tutorial;
+                save::store_save_game(&save_game, &mut save_storage);
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            MenuAction::Quit => {
+                app_exit.send(AppExit);
+            }
+        }
+    }
+}
+
+fn save_keymap(keymap: &Keymap, storage: &mut KeymapStorage) {
+    if let Err(err) = storage.write(keymap) {
+        bevy::log::warn!("Could not save the controls: {}", err);
+    }
+}
+
+pub fn is_rebinding(rebinding: Res<Rebinding>) -> bool {
+    rebinding.action.is_some()
+}
+
+/// Binds the next pressed key or gamepad button to the action waiting for
+/// one.  The press is consumed so it doesn't also navigate the menu.
+pub fn capture_binding(
+    mut keys: ResMut<ButtonInput<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    mut gamepad_buttons: ResMut<ButtonInput<GamepadButton>>,
+    mut rebinding: ResMut<Rebinding>,
+    mut keymap: ResMut<Keymap>,
+    mut storage: ResMut<KeymapStorage>,
+) {
+    let Some(action) = rebinding.action else {
+        return;
+    };
+    let key = keys.get_just_pressed().next().copied();
+    let button = gamepad_buttons
+        .get_just_pressed()
+        .find(|button| gamepads.contains(button.gamepad))
+        .copied();
+    let binding = match (key, button) {
+        (Some(KeyCode::Escape), _) => {
+            keys.clear_just_pressed(KeyCode::Escape);
+            rebinding.action = None;
+            rebinding.message.clear();
+            return;
+        }
+        (Some(key), _) => {
+            keys.clear_just_pressed(key);
+            Binding::Key(key)
+        }
+        (None, Some(button)) => {
+            gamepad_buttons.clear_just_pressed(button);
+            Binding::Gamepad(button.button_type)
+        }
+        (None, None) => return,
+    };
+    rebinding.action = None;
+    rebinding.message = match keymap.rebind(action, binding) {
+        Ok(()) => {
+            save_keymap(&keymap, &mut storage);
+            format!("{} is now bound to {}", action.label(), binding)
+        }
+        Err(err) => err.to_string(),
+    };
+}
+
+/// Shows the current bindings on the buttons of the controls screen.
+pub fn update_controls_labels(
+    keymap: Res<Keymap>,
+    rebinding: Res<Rebinding>,
+    buttons: Query<(&MenuButton, &Children)>,
+    mut texts: Query<&mut Text, Without<ControlsMessage>>,
+    mut message_query: Query<&mut Text, With<ControlsMessage>>,
+) {
+    for (button, children) in buttons.iter() {
+        let MenuAction::Rebind(action) = button.action else {
+            continue;
+        };
+        let label = if rebinding.action == Some(action) {
+            format!("{}: ...", action.label())
+        } else {
+            format!("{}: {}", action.label(), keymap.describe(action))
+        };
+        let mut texts = texts.iter_many_mut(children);
+        while let Some(mut text) = texts.fetch_next() {
+            if text.sections[0].value != label {
+                text.sections[0].value = label.clone();
+            }
+        }
+    }
+    for mut text in message_query.iter_mut() {
+        if text.sections[0].value != rebinding.message {
+            text.sections[0].value = rebinding.message.clone();
+        }
+    }
+}
+
+pub fn update_skip_tutorial_label(
+    save_game: Res<SaveGame>,
+    buttons: Query<(&MenuButton, &Children)>,
+    mut texts: Query<&mut Text>,
+) {
+    let label = format!(
+        "Skip Tutorial: {}",
+        if save_game.settings.skip_tutorial {
+            "On"
+        } else {
+            "Off"
+        }
+    );
+    for (_, children) in buttons
+        .iter()
+        .filter(|(button, _)| button.action == MenuAction::ToggleSkipTutorial)
+    {
+        let mut texts = texts.iter_many_mut(children);
+        while let Some(mut text) = texts.fetch_next() {
+            if text.sections[0].value != label {
+                text.sections[0].value = label.clone();
+            }
+        }
+    }
+}
+
+/// Opens and closes the pause menu with escape or the start button.
+pub fn toggle_pause(
+    keys: Res<ButtonInput<KeyCode>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<ButtonInput<GamepadButton>>,
+    pause_state: Res<State<PauseState>>,
+    mut next_pause_state: ResMut<NextState<PauseState>>,
+) {
+    let start_pressed = gamepads.iter().any(|gamepad| {
+        gamepad_buttons.just_pressed(GamepadButton::new(gamepad, GamepadButtonType::Start))
+    });
+    if keys.just_pressed(KeyCode::Escape) || start_pressed {
+        next_pause_state.set(match pause_state.get() {
+            PauseState::Running => PauseState::Paused,
+            PauseState::Paused => PauseState::Running,
+            // The results can only be closed with their buttons.
+            PauseState::Results => return,
+        });
+    }
+}
+
+/// Stops the physics and the game clock, which also halts the stars and
+/// every timer.
+pub fn freeze_game(
+    mut rapier_configuration: ResMut<RapierConfiguration>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    rapier_configuration.physics_pipeline_active = false;
+    time.pause();
+}
+
+pub fn unfreeze_game(
+    mut rapier_configuration: ResMut<RapierConfiguration>,
+    mut time: ResMut<Time<Virtual>>,
+) {
+    rapier_configuration.physics_pipeline_active = true;
+    time.unpause();
+}
+
+pub struct MenuPlugin;
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_state::<PauseState>()
+            .init_resource::<MenuSelection>()
+            .init_resource::<Rebinding>()
+            .add_event::<MenuActionEvent>()
+            // CleanupPlugin can only be added once per state type, so the
+            // other game states get their cleanup system directly.
+            .add_plugins(despawn::CleanupPlugin(PauseState::Paused))
+            .add_systems(
+                OnExit(GameState::MainMenu),
+                despawn::cleanup_system(GameState::MainMenu),
+            )
+            .add_systems(
+                OnExit(GameState::GameOver),
+                despawn::cleanup_system(GameState::GameOver),
+            )
+            .add_systems(
+                OnExit(GameState::Controls),
+                despawn::cleanup_system(GameState::Controls),
+            )
+            .add_systems(
+                OnExit(GameState::LevelSelect),
+                despawn::cleanup_system(GameState::LevelSelect),
+            )
+            .add_systems(OnEnter(GameState::MainMenu), spawn_main_menu)
+            .add_systems(OnEnter(GameState::Controls), spawn_controls_menu)
+            .add_systems(OnEnter(GameState::LevelSelect), spawn_level_select)
+            .add_systems(OnEnter(GameState::GameOver), spawn_game_over)
+            .add_systems(OnEnter(PauseState::Paused), (spawn_pause_menu, freeze_game))
+            .add_systems(OnExit(PauseState::Paused), unfreeze_game)
+            .add_systems(
+                OnEnter(PauseState::Results),
+                (spawn_results_menu, freeze_game),
+            )
+            .add_systems(
+                OnExit(PauseState::Results),
+                (despawn::cleanup_system(PauseState::Results), unfreeze_game),
+            )
+            .add_systems(
+                Update,
+                (
+                    capture_binding.run_if(in_state(GameState::Controls)),
+                    menu_navigation
+                        .run_if(any_with_component::<MenuButton>.and_then(not(is_rebinding))),
+                    highlight_selection,
+                    menu_action_handler,
+                    update_controls_labels.run_if(in_state(GameState::Controls)),
+                    update_skip_tutorial_label.run_if(in_state(GameState::MainMenu)),
+                )
+                    .chain(),
+            )
+            .add_systems(Update, toggle_pause.run_if(in_state(GameState::InGame)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ship::Player, stars::StarMaterialSettings, storage::Storage, test_support::TestGame,
+        InGameState,
+    };
+    use some_bevy_tools::health::Health;
+
+    #[test]
+    fn escape_pauses_and_resumes() {
+        let mut game = TestGame::new();
+        game.tap_key(KeyCode::Escape);
+        assert_eq!(game.state::<PauseState>(), PauseState::Paused);
+        assert!(
+            !game
+                .app
+                .world
+                .resource::<RapierConfiguration>()
+                .physics_pipeline_active
+        );
+
+        game.app
+            .world
+            .resource_mut::<StarMaterialSettings>()
+            .desired_speed_x = 1000.0;
+        game.step(10);
+        assert_eq!(
+            game.app.world.resource::<StarMaterialSettings>().speed_x,
+            0.0
+        );
+
+        game.tap_key(KeyCode::Escape);
+        assert_eq!(game.state::<PauseState>(), PauseState::Running);
+        assert!(
+            game.app
+                .world
+                .resource::<RapierConfiguration>()
+                .physics_pipeline_active
+        );
+    }
+
+    #[test]
+    fn last_death_leads_to_game_over_and_retry() {
+        let mut game = TestGame::new();
+        game.app.world.resource_mut::<InGameState>().lives = 1;
+        let player = game.player();
+        game.app
+            .world
+            .get_mut::<Health>(player)
+            .unwrap()
+            .modify(-1000.0);
+        game.step(5);
+        assert_eq!(game.state::<GameState>(), GameState::GameOver);
+
+        game.tap_key(KeyCode::Enter);
+        game.step(1);
+        assert_eq!(game.state::<GameState>(), GameState::InGame);
+        let players = game
+            .app
+            .world
+            .query_filtered::<(), With<Player>>()
+            .iter(&game.app.world)
+            .count();
+        assert_eq!(players, 1);
+    }
+
+    #[test]
+    fn start_game_from_main_menu() {
+        let mut game = TestGame::new();
+        game.app
+            .world
+            .insert_resource(NextState(Some(GameState::MainMenu)));
+        game.step(1);
+        assert_eq!(game.state::<GameState>(), GameState::MainMenu);
+
+        game.tap_key(KeyCode::Enter);
+        assert_eq!(game.state::<GameState>(), GameState::InGame);
+    }
+
+    #[test]
+    fn play_completed_level_from_level_select() {
+        let mut game = TestGame::new();
+        game.app
+            .world
+            .resource_mut::<SaveGame>()
+            .completed
+            .extend([Level::Tutorial, Level::Level1]);
+        game.app
+            .world
+            .insert_resource(NextState(Some(GameState::MainMenu)));
+        game.step(1);
+        game.tap_key(KeyCode::ArrowDown);
+        game.tap_key(KeyCode::Enter);
+        game.step(1);
+        assert_eq!(game.state::<GameState>(), GameState::LevelSelect);
+
+        // The levels are listed in order, the second one is level 1.
+        game.tap_key(KeyCode::ArrowDown);
+        game.tap_key(KeyCode::Enter);
+        game.step(1);
+        assert_eq!(game.state::<GameState>(), GameState::InGame);
+        assert_eq!(
+            game.app.world.resource::<InGameState>().level,
+            Level::Level1
+        );
+    }
+
+    #[test]
+    fn rebind_on_controls_screen() {
+        let mut game = TestGame::new();
+        game.app
+            .world
+            .insert_resource(NextState(Some(GameState::MainMenu)));
+        game.step(1);
+        game.tap_key(KeyCode::ArrowDown);
+        game.tap_key(KeyCode::Enter);
+        game.step(1);
+        assert_eq!(game.state::<GameState>(), GameState::Controls);
+
+        // The first button rebinds moving up.
+        game.tap_key(KeyCode::Enter);
+        game.tap_key(KeyCode::KeyI);
+        assert_eq!(
+            game.app
+                .world
+                .resource::<Keymap>()
+                .bindings(BindableAction::MoveUp)[0],
+            Binding::Key(KeyCode::KeyI)
+        );
+        assert_eq!(game.app.world.resource::<MenuSelection>().0, 0);
+        let KeymapStorage(Storage::Memory(Some(stored))) =
+            game.app.world.resource::<KeymapStorage>()
+        else {
+            panic!("keymap not stored");
+        };
+        assert!(stored.contains("move_up = key KeyI"));
+
+        // Keys of other actions are refused.
+        game.tap_key(KeyCode::Enter);
+        game.tap_key(KeyCode::KeyS);
+        assert_eq!(
+            game.app
+                .world
+                .resource::<Keymap>()
+                .action_for(Binding::Key(KeyCode::KeyS)),
+            Some(BindableAction::MoveDown)
+        );
+        assert!(game
+            .app
+            .world
+            .resource::<Rebinding>()
+            .message
+            .contains("Move down"));
+        assert!(game.app.world.resource::<Rebinding>().action.is_none());
+        // The refused key didn't navigate the menu either.
+        assert_eq!(game.app.world.resource::<MenuSelection>().0, 0);
+    }
+}
@@ -0,0 +1,397 @@
+use std::fmt;
+use std::str::FromStr;
+
+use bevy::prelude::*;
+use some_bevy_tools::{
+    collision_detection::{self, CollisionEventStart},
+    despawn::{self, AutoDespawn},
+    health::{DeathEvent, Health, HealthMarker},
+    physics2d, range,
+};
+
+use crate::{
+    assets::ImageAssets,
+    bullet,
+    map_builder::{MapDraftError, TileMarker},
+    menu::PauseState,
+    random::GameRng,
+    ship::{Player, Ship},
+    weapon::Weapon,
+    GameState, InGameState,
+};
+
+/// Health a health pack restores if its definition doesn't set it.
+pub const DEFAULT_HEALTH_PACK: f32 = 30.0;
+
+/// Score of a gem if its definition doesn't set it.
+pub const DEFAULT_GEM_VALUE: u32 = 10;
+
+/// What happens to the player who collects a pickup.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PickupKind {
+    /// Restores the given health.
+    Health(f32),
+
+    /// Makes the current weapon of the player stronger.
+    WeaponUpgrade,
+
+    /// Adds the given value to the score.
+    Gem(u32),
+
+    /// The exit of a map stays locked until every key of it is collected.
+    Key,
+}
+
+impl Default for PickupKind {
+    fn default() -> Self {
+        PickupKind::Gem(DEFAULT_GEM_VALUE)
+    }
+}
+
+impl PickupKind {
+    /// The name used in map files, without the value.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PickupKind::Health(_) => "health",
+            PickupKind::WeaponUpgrade => "upgrade",
+            PickupKind::Gem(_) => "gem",
+            PickupKind::Key => "key",
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            PickupKind::Health(_) => Color::rgb(0.3, 1.0, 0.4),
+            PickupKind::WeaponUpgrade => Color::rgb(1.0, 0.5, 0.1),
+            PickupKind::Gem(_) => Color::rgb(0.4, 0.8, 1.0),
+            PickupKind::Key => Color::rgb(1.0, 0.9, 0.2),
+        }
+    }
+}
+
+/// Parses the name of a pickup, values are the defaults.
+impl FromStr for PickupKind {
+    type Err = MapDraftError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "health" => Ok(PickupKind::Health(DEFAULT_HEALTH_PACK)),
+            "upgrade" => Ok(PickupKind::WeaponUpgrade),
+            "gem" => Ok(PickupKind::Gem(DEFAULT_GEM_VALUE)),
+            "key" => Ok(PickupKind::Key),
+            _ => Err(MapDraftError::UnknownPickup(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for PickupKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PickupKind::Health(health) => write!(f, "health {}", health),
+            PickupKind::Gem(value) => write!(f, "gem {}", value),
+            PickupKind::WeaponUpgrade | PickupKind::Key => write!(f, "{}", self.name()),
+        }
+    }
+}
+
+/// Pickups and their chances to drop, at most one of them drops at a time.
+///
+/// Written as `<pickup>:<chance>` entries, like `health:0.1 gem:0.3`, where
+/// the chances add up to at most 1.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct LootTable(pub Vec<(PickupKind, f32)>);
+
+impl LootTable {
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn roll(&self, rng: &mut GameRng) -> Option<PickupKind> {
+        let mut roll = rng.next_f32();
+        for (kind, chance) in &self.0 {
+            if roll < *chance {
+                return Some(*kind);
+            }
+            roll -= chance;
+        }
+        None
+    }
+
+    pub fn parse<'a>(entries: impl IntoIterator<Item = &'a str>) -> Result<Self, MapDraftError> {
+        let invalid = |entry: &str| MapDraftError::InvalidTileDefinition(entry.to_string());
+        let table = entries
+            .into_iter()
+            .map(|entry| {
+                let (kind, chance) = entry.split_once(':').ok_or_else(|| invalid(entry))?;
+                let chance = chance.parse::<f32>().map_err(|_| invalid(entry))?;
+                if !(0.0..=1.0).contains(&chance) {
+                    return Err(invalid(entry));
+                }
+                Ok((kind.parse()?, chance))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if table.iter().map(|(_, chance)| chance).sum::<f32>() > 1.0 {
+            return Err(invalid("chances add up to more than 1"));
+        }
+        Ok(Self(table))
+    }
+}
+
+impl fmt::Display for LootTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (kind, chance)) in self.0.iter().enumerate() {
+            if index > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}:{}", kind.name(), chance)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collected by the player who flies through it.
+#[derive(Component, Clone, Copy, Default, PartialEq, Debug)]
+pub struct Pickup(pub PickupKind);
+
+/// What the entity drops once it's destroyed.
+#[derive(Component, Clone, Default)]
+pub struct Loot(pub LootTable);
+
+pub fn spawn_pickup(
+    commands: &mut Commands,
+    image_assets: &ImageAssets,
+    position: Vec2,
+    kind: PickupKind,
+) -> Entity {
+    commands
+        .spawn((
+            SpriteBundle {
+                texture: image_assets.bullet.clone(),
+                transform: Transform::from_translation(position.extend(0.0)),
+                sprite: Sprite {
+                    color: kind.color(),
+                    custom_size: Some(Vec2::new(24.0, 24.0)),
+                    ..default()
+                },
+                ..default()
+            },
+            physics2d::PhysicsBundle::trigger(50.0, 50.0, 0.6),
+            Pickup(kind),
+            despawn::Cleanup(GameState::InGame),
+        ))
+        .id()
+}
+
+/// Rolls the loot of the destroyed and drops it where they were.
+pub fn drop_loot(
+    mut commands: Commands,
+    image_assets: Res<ImageAssets>,
+    mut rng: ResMut<GameRng>,
+    mut death_events: EventReader<DeathEvent>,
+    loot_query: Query<(&Transform, &Loot, Option<&TileMarker>)>,
+) {
+    for event in death_events.read() {
+        let Ok((transform, Loot(table), marker)) = loot_query.get(event.entity) else {
+            continue;
+        };
+        // Death events repeat, the loot is only rolled once.
+        commands.entity(event.entity).remove::<Loot>();
+        let Some(kind) = table.roll(&mut rng) else {
+            continue;
+        };
+        let pickup = spawn_pickup(
+            &mut commands,
+            &image_assets,
+            transform.translation.xy(),
+            kind,
+        );
+        // Dropped keys still belong to the map and lock its exit.
+        if let Some(marker) = marker {
+            commands.entity(pickup).insert(TileMarker(marker.0));
+        }
+    }
+}
+
+pub fn collect_pickups(
+    mut commands: Commands,
+    mut pickup_events: EventReader<CollisionEventStart<Ship, Pickup>>,
+    pickup_query: Query<&Pickup>,
+    mut player_query: Query<(&mut Health, &mut Weapon), With<Player>>,
+    mut in_game_state: ResMut<InGameState>,
+) {
+    for CollisionEventStart(ship, pickup_entity, _) in pickup_events.read() {
+        let Ok(Pickup(kind)) = pickup_query.get(*pickup_entity) else {
+            continue;
+        };
+        let Ok((mut health, mut weapon)) = player_query.get_mut(*ship) else {
+            continue;
+        };
+        bevy::log::info!("Picked up {}", kind);
+        match kind {
+            PickupKind::Health(amount) => {
+                health.modify(*amount);
+            }
+            PickupKind::WeaponUpgrade => weapon.upgrade(),
+            PickupKind::Gem(value) => in_game_state.score += value,
+            PickupKind::Key => {}
+        }
+        commands
+            .entity(*pickup_entity)
+            .remove::<Pickup>()
+            .insert(AutoDespawn::with_frames(0));
+    }
+}
+
+pub struct PickupPlugin;
+impl Plugin for PickupPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(collision_detection::CollisionDetectionPlugin::<Ship, Pickup>::default())
+            .add_systems(
+                Update,
+                (
+                    // The dead are still around to tell where they were.
+                    drop_loot
+                        .after(range::update_range::<HealthMarker>)
+                        .before(bullet::despawn_the_dead),
+                    collect_pickups,
+                )
+                    .run_if(in_state(GameState::InGame).and_then(in_state(PauseState::Running))),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_builder::TileType;
+    use crate::test_support::{TestGame, OPEN_SPACE};
+
+    /// Spawns a map of the single tile and returns its id.
+    fn spawn_tile(game: &mut TestGame, tile_type: TileType) -> uuid::Uuid {
+        game.spawn_map("P", move |_| Some(tile_type.clone()))
+    }
+
+    fn pickup_count(game: &mut TestGame) -> usize {
+        game.app
+            .world
+            .query::<&Pickup>()
+            .iter(&game.app.world)
+            .count()
+    }
+
+    #[test]
+    fn loot_table_rolls_by_chance() {
+        let table = LootTable::parse(["health:0.25", "gem:0.5"]).unwrap();
+        assert_eq!(table.to_string(), "health:0.25 gem:0.5");
+        let mut rng = GameRng::new(7);
+        let mut drops = [0i32; 3];
+        for _ in 0..4000 {
+            match table.roll(&mut rng) {
+                Some(PickupKind::Health(_)) => drops[0] += 1,
+                Some(PickupKind::Gem(_)) => drops[1] += 1,
+                _ => drops[2] += 1,
+            }
+        }
+        for (drops, expected) in drops.into_iter().zip([1000, 2000, 1000]) {
+            assert!((drops - expected).abs() < 150, "{}", drops);
+        }
+
+        assert_eq!(LootTable::default().roll(&mut rng), None);
+        assert!(LootTable::parse(["health:0.6", "key:0.6"]).is_err());
+        assert!(LootTable::parse(["health"]).is_err());
+        assert!(LootTable::parse(["coin:0.1"]).is_err());
+    }
+
+    #[test]
+    fn health_pack_heals_the_player() {
+        let mut game = TestGame::new();
+        let player = game.player();
+        game.app
+            .world
+            .get_mut::<Health>(player)
+            .unwrap()
+            .modify(-50.0);
+        let damaged = game.app.world.get::<Health>(player).unwrap().get();
+        spawn_tile(&mut game, TileType::Pickup(PickupKind::Health(20.0)));
+        game.teleport_player(OPEN_SPACE);
+        game.step(5);
+
+        let health = game.app.world.get::<Health>(player).unwrap().get();
+        assert_eq!(health, damaged + 20.0);
+        assert_eq!(pickup_count(&mut game), 0);
+    }
+
+    #[test]
+    fn gems_add_to_the_score_and_upgrades_to_the_weapon() {
+        let mut game = TestGame::new();
+        spawn_tile(&mut game, TileType::Pickup(PickupKind::Gem(25)));
+        game.teleport_player(OPEN_SPACE);
+        game.step(5);
+        assert_eq!(game.app.world.resource::<InGameState>().score, 25);
+
+        let player = game.player();
+        let damage = game.app.world.get::<Weapon>(player).unwrap().damage;
+        spawn_tile(&mut game, TileType::Pickup(PickupKind::WeaponUpgrade));
+        game.teleport_player(OPEN_SPACE + Vec2::new(500.0, 0.0));
+        game.step(2);
+        game.teleport_player(OPEN_SPACE);
+        game.step(5);
+        let weapon = game.app.world.get::<Weapon>(player).unwrap();
+        assert_eq!(weapon.level, 1);
+        assert!(weapon.damage > damage);
+        assert_eq!(pickup_count(&mut game), 0);
+    }
+
+    #[test]
+    fn destroyed_rock_drops_its_loot() {
+        let mut game = TestGame::new();
+        let loot = LootTable::parse(["key:1"]).unwrap();
+        let map = spawn_tile(&mut game, TileType::Rock(10.0, loot));
+        let rock = game
+            .app
+            .world
+            .query_filtered::<(Entity, &TileMarker), With<Loot>>()
+            .iter(&game.app.world)
+            .find(|(_, marker)| marker.0 == map)
+            .map(|(rock, _)| rock)
+            .unwrap();
+        game.app
+            .world
+            .get_mut::<Health>(rock)
+            .unwrap()
+            .modify(-100.0);
+        game.step(5);
+
+        let drops = game
+            .app
+            .world
+            .query::<(&Pickup, &TileMarker, &Transform)>()
+            .iter(&game.app.world)
+            .filter(|(_, marker, _)| marker.0 == map)
+            .map(|(pickup, marker, transform)| (*pickup, marker.0, transform.translation.xy()))
+            .collect::<Vec<_>>();
+        assert_eq!(drops, vec![(Pickup(PickupKind::Key), map, OPEN_SPACE)]);
+    }
+
+    #[test]
+    fn pickup_tile_definitions() {
+        for definition in [
+            "pickup health 20",
+            "pickup gem 5",
+            "pickup upgrade",
+            "pickup key",
+            "rock 10 loot health:0.1 gem:0.2",
+            "rock 10",
+        ] {
+            let tile_type = definition.parse::<TileType>().unwrap();
+            assert_eq!(tile_type.to_string(), definition);
+        }
+        assert!(matches!(
+            "pickup health".parse::<TileType>(),
+            Ok(TileType::Pickup(PickupKind::Health(DEFAULT_HEALTH_PACK)))
+        ));
+        assert!("pickup key 3".parse::<TileType>().is_err());
+        assert!("pickup coin".parse::<TileType>().is_err());
+        assert!("rock 10 20".parse::<TileType>().is_err());
+    }
+}
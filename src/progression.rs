@@ -0,0 +1,187 @@
+#![allow(clippy::type_complexity)]
+
+use bevy::prelude::*;
+use some_bevy_tools::despawn;
+
+use crate::{
+    maps::{Level, LevelComplete},
+    script::{RunningScript, ScriptStep},
+    GameState,
+};
+
+/// The level the next run starts in, as picked on the level select screen.
+/// Without one the run continues where the save game left off.
+#[derive(Resource, Default)]
+pub struct StartLevel(pub Option<Level>);
+
+/// Marks the script which takes the player from a finished level to the
+/// next one.
+#[derive(Component)]
+pub struct LevelTransition;
+
+/// Flies the ship into deep space and shows the logo, then spawns the next
+/// level around the ship or ends the run after the last level.
+pub fn transition_steps(next: Option<Level>) -> Vec<ScriptStep> {
+    let mut steps = vec![
+        ScriptStep::Stars {
+            speed: 10000.0,
+            acceleration: 2000.0,
+        },
+        ScriptStep::LockControls,
+        ScriptStep::ShipHeading(Vec2::X),
+        ScriptStep::ShipVelocity(Vec2::new(300.0, 0.0)),
+        ScriptStep::CameraMove(310.0),
+        ScriptStep::Wait(2.0),
+        ScriptStep::ShowOverlay,
+        ScriptStep::Wait(7.0),
+    ];
+    match next {
+        Some(level) => steps.extend([
+            ScriptStep::ShipVelocity(Vec2::ZERO),
+            ScriptStep::HideOverlay,
+            ScriptStep::SpawnMap(level),
+            ScriptStep::CameraFollow,
+            ScriptStep::UnlockControls,
+            ScriptStep::Stars {
+                speed: 0.0,
+                acceleration: 20000.0,
+            },
+        ]),
+        None => steps.push(ScriptStep::FinishRun),
+    }
+    steps
+}
+
+/// Starts the transition to the next level, once per finished level.
+pub fn start_level_transition(
+    mut commands: Commands,
+    mut complete_events: EventReader<LevelComplete>,
+    transition_query: Query<(), With<LevelTransition>>,
+) {
+    let mut transitioning = !transition_query.is_empty();
+    for LevelComplete(level) in complete_events.read() {
+        if transitioning {
+            continue;
+        }
+        transitioning = true;
+        let next = level.info().next;
+        bevy::log::info!("Leaving {} for {:?}", level, next);
+        commands.spawn((
+            RunningScript::new("transition", transition_steps(next)),
+            LevelTransition,
+            despawn::Cleanup(GameState::InGame),
+        ));
+    }
+}
+
+pub struct ProgressionPlugin;
+impl Plugin for ProgressionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StartLevel>()
+            .add_event::<LevelComplete>()
+            // The transition script waits for the results screen to close,
+            // but the completion has to be picked up while it's open.
+            .add_systems(
+                Update,
+                start_level_transition.run_if(in_state(GameState::InGame)),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        goal::{Exit, LevelStats},
+        map_builder::TileMarker,
+        menu::PauseState,
+        save::SaveGame,
+        stars,
+        test_support::TestGame,
+        InGameState,
+    };
+
+    #[test]
+    fn tutorial_flies_on_to_level_1() {
+        let mut game = TestGame::new();
+        let tutorial = game.app.world.resource::<InGameState>().active_map;
+        let position = game.trigger_position("DeepSpace");
+        game.teleport_player(position);
+        game.step(60);
+        assert!(game.app.world.resource::<InGameState>().block_controls);
+        assert!(game
+            .app
+            .world
+            .resource::<SaveGame>()
+            .completed
+            .contains(&Level::Tutorial));
+        assert_eq!(game.state::<PauseState>(), PauseState::Results);
+
+        // The transition goes on once the results are closed.
+        game.tap_key(KeyCode::Enter);
+        assert_eq!(game.state::<PauseState>(), PauseState::Running);
+        game.step(60 * 10);
+        let in_game_state = game.app.world.resource::<InGameState>();
+        assert_eq!(in_game_state.level, Level::Level1);
+        assert!(!in_game_state.block_controls);
+        assert_eq!(
+            game.app
+                .world
+                .resource::<stars::StarMaterialSettings>()
+                .desired_speed_x,
+            0.0
+        );
+        assert!(game
+            .app
+            .world
+            .query::<&RunningScript>()
+            .iter(&game.app.world)
+            .next()
+            .is_none());
+        let map_origin = game.app.world.resource::<InGameState>().map_origin;
+        assert_eq!(
+            game.trigger_position("ArenaAhead") - map_origin,
+            Vec2::new(0.0, 100.0)
+        );
+        // The tutorial is unloaded on the way.
+        assert!(tutorial.is_some());
+        assert!(!game
+            .app
+            .world
+            .query::<&TileMarker>()
+            .iter(&game.app.world)
+            .any(|marker| Some(marker.0) == tutorial));
+    }
+
+    #[test]
+    fn reaching_the_exit_of_the_last_level_ends_the_run() {
+        let mut game = TestGame::with_setup(|app| {
+            app.insert_resource(StartLevel(Some(Level::Level1)));
+        });
+        assert_eq!(
+            game.app.world.resource::<InGameState>().level,
+            Level::Level1
+        );
+        let exit = game
+            .app
+            .world
+            .query_filtered::<&Transform, With<Exit>>()
+            .single(&game.app.world)
+            .translation
+            .xy();
+        game.teleport_player(exit);
+        game.step(5);
+        assert!(game.app.world.resource::<LevelStats>().completed);
+        assert!(game
+            .app
+            .world
+            .resource::<SaveGame>()
+            .completed
+            .contains(&Level::Level1));
+        assert_eq!(game.state::<PauseState>(), PauseState::Results);
+
+        game.tap_key(KeyCode::Enter);
+        game.step(60 * 10);
+        assert_eq!(game.state::<GameState>(), GameState::MainMenu);
+    }
+}
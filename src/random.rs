@@ -0,0 +1,65 @@
+use bevy::prelude::*;
+use uuid::Uuid;
+
+/// Random numbers for the game logic.
+///
+/// Everything random in a run has to come from here so a replay with the
+/// same seed plays out the same way.
+#[derive(Resource, Clone, Debug)]
+pub struct GameRng {
+    seed: u64,
+    state: u64,
+}
+
+impl Default for GameRng {
+    fn default() -> Self {
+        Self::from_entropy()
+    }
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, state: seed }
+    }
+
+    /// Starts with a seed nobody picked.
+    pub fn from_entropy() -> Self {
+        Self::new(Uuid::new_v4().as_u64_pair().0)
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// SplitMix64, small and good enough for games.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0.0..1.0`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_numbers() {
+        let mut a = GameRng::new(42);
+        let mut b = GameRng::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+            let value = a.next_f32();
+            assert!((0.0..1.0).contains(&value));
+            assert_eq!(value, b.next_f32());
+        }
+        assert_ne!(GameRng::new(43).next_u64(), GameRng::new(42).next_u64());
+    }
+}
@@ -0,0 +1,528 @@
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy_rapier2d::prelude::*;
+use some_bevy_tools::{
+    controller_2d::TopDownAction,
+    input::{self, ActionEvent},
+};
+use thiserror::Error;
+
+use crate::{
+    keymap::{self, LeftStick},
+    menu::PauseState,
+    random::GameRng,
+    GameState,
+};
+
+/// Time which passes with every frame while recording or replaying.
+pub const FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+#[derive(Error, Debug)]
+pub enum ReplayError {
+    #[error("could not access replay file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("line {0}: expected `key = value`, `<frame> <action>` or `<frame> stick <x> <y>`")]
+    InvalidLine(usize),
+
+    #[error("line {line}: unknown action `{action}`")]
+    UnknownAction { line: usize, action: String },
+
+    #[error("header field `{0}` is missing")]
+    MissingField(&'static str),
+
+    #[error("expected `--record <file>` or `--replay <file>`")]
+    InvalidArguments,
+}
+
+fn action_name(action: &TopDownAction) -> &'static str {
+    match action {
+        TopDownAction::MoveUp => "move_up",
+        TopDownAction::MoveDown => "move_down",
+        TopDownAction::MoveLeft => "move_left",
+        TopDownAction::MoveRight => "move_right",
+        TopDownAction::Action => "action",
+        TopDownAction::Action2 => "action2",
+        TopDownAction::Exit => "exit",
+    }
+}
+
+fn parse_action(name: &str) -> Option<TopDownAction> {
+    match name {
+        "move_up" => Some(TopDownAction::MoveUp),
+        "move_down" => Some(TopDownAction::MoveDown),
+        "move_left" => Some(TopDownAction::MoveLeft),
+        "move_right" => Some(TopDownAction::MoveRight),
+        "action" => Some(TopDownAction::Action),
+        "action2" => Some(TopDownAction::Action2),
+        "exit" => Some(TopDownAction::Exit),
+        _ => None,
+    }
+}
+
+/// The actions of one run from entering [`GameState::InGame`] on.
+///
+/// Frames only count while the game is running, so pausing doesn't show up.
+/// The file has a header, a `---` separator and one action or stick
+/// movement per line:
+///
+/// ```text
+/// seed = 1234
+/// frames = 600
+/// ---
+/// 12 move_up
+/// 12 action
+/// 20 stick -0.3 0.6
+/// ```
+#[derive(Clone, PartialEq)]
+pub struct Replay {
+    pub seed: u64,
+
+    /// Frames the run lasted.
+    pub frames: u64,
+
+    /// Actions with the frame they happened in, in order.
+    pub actions: Vec<(u64, TopDownAction)>,
+
+    /// Left stick positions from the frame they changed in on, in order.
+    pub sticks: Vec<(u64, Vec2)>,
+}
+
+impl Replay {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            frames: 0,
+            actions: Vec::new(),
+            sticks: Vec::new(),
+        }
+    }
+
+    /// Position of the left stick in the given frame.
+    pub fn stick(&self, frame: u64) -> Vec2 {
+        let changes = self
+            .sticks
+            .partition_point(|(changed, _)| *changed <= frame);
+        changes
+            .checked_sub(1)
+            .map_or(Vec2::ZERO, |index| self.sticks[index].1)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, ReplayError> {
+        let mut seed = None;
+        let mut frames = None;
+        let mut actions = Vec::new();
+        let mut sticks = Vec::new();
+        let mut in_header = true;
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if in_header {
+                if line == "---" {
+                    in_header = false;
+                    continue;
+                }
+                let (key, value) = line
+                    .split_once('=')
+                    .ok_or(ReplayError::InvalidLine(line_number))?;
+                let value = value
+                    .trim()
+                    .parse::<u64>()
+                    .map_err(|_| ReplayError::InvalidLine(line_number))?;
+                match key.trim() {
+                    "seed" => seed = Some(value),
+                    "frames" => frames = Some(value),
+                    _ => return Err(ReplayError::InvalidLine(line_number)),
+                }
+                continue;
+            }
+            let (frame, action) = line
+                .split_once(' ')
+                .ok_or(ReplayError::InvalidLine(line_number))?;
+            let frame = frame
+                .parse::<u64>()
+                .map_err(|_| ReplayError::InvalidLine(line_number))?;
+            let action = action.trim();
+            if let Some(stick) = action.strip_prefix("stick ") {
+                let (x, y) = stick
+                    .trim()
+                    .split_once(' ')
+                    .ok_or(ReplayError::InvalidLine(line_number))?;
+                let (Ok(x), Ok(y)) = (x.parse::<f32>(), y.trim().parse::<f32>()) else {
+                    return Err(ReplayError::InvalidLine(line_number));
+                };
+                sticks.push((frame, Vec2::new(x, y)));
+                continue;
+            }
+            let action = parse_action(action).ok_or_else(|| ReplayError::UnknownAction {
+                line: line_number,
+                action: action.to_string(),
+            })?;
+            actions.push((frame, action));
+        }
+        actions.sort_by_key(|(frame, _)| *frame);
+        sticks.sort_by_key(|(frame, _)| *frame);
+        Ok(Self {
+            seed: seed.ok_or(ReplayError::MissingField("seed"))?,
+            frames: frames.ok_or(ReplayError::MissingField("frames"))?,
+            actions,
+            sticks,
+        })
+    }
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# Replay, written by the game.")?;
+        writeln!(f, "seed = {}", self.seed)?;
+        writeln!(f, "frames = {}", self.frames)?;
+        writeln!(f, "---")?;
+        for (frame, action) in &self.actions {
+            writeln!(f, "{} {}", frame, action_name(action))?;
+        }
+        for (frame, stick) in &self.sticks {
+            writeln!(f, "{} stick {} {}", frame, stick.x, stick.y)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Resource, Default)]
+pub enum ReplayMode {
+    #[default]
+    Off,
+
+    /// Records every run and writes it to the file, if there is one, once
+    /// the run ends.
+    Recording {
+        path: Option<PathBuf>,
+        replay: Replay,
+    },
+
+    /// Feeds the actions and the stick of the replay to the game instead of
+    /// the player's.
+    Playing { replay: Replay, next: usize },
+}
+
+impl ReplayMode {
+    /// Reads `--record <file>` or `--replay <file>` from the command line.
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> Result<Self, ReplayError> {
+        match (args.next().as_deref(), args.next(), args.next()) {
+            (None, _, _) => Ok(ReplayMode::Off),
+            (Some("--record"), Some(path), None) => Ok(ReplayMode::Recording {
+                path: Some(path.into()),
+                replay: Replay::new(0),
+            }),
+            (Some("--replay"), Some(path), None) => Ok(ReplayMode::Playing {
+                replay: Replay::parse(&std::fs::read_to_string(path)?)?,
+                next: 0,
+            }),
+            _ => Err(ReplayError::InvalidArguments),
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        matches!(self, ReplayMode::Playing { .. })
+    }
+}
+
+/// Run condition for everything which would make a replay differ from the
+/// recorded run, like loading the save game.
+pub fn is_off(mode: Res<ReplayMode>) -> bool {
+    matches!(*mode, ReplayMode::Off)
+}
+
+/// Frames the current run is running.
+#[derive(Resource, Default)]
+pub struct ReplayClock(pub u64);
+
+/// Seeds the random numbers and restarts the clock for a new run.
+pub fn start_run(
+    mut mode: ResMut<ReplayMode>,
+    mut rng: ResMut<GameRng>,
+    mut clock: ResMut<ReplayClock>,
+) {
+    clock.0 = 0;
+    match mode.as_mut() {
+        ReplayMode::Off => *rng = GameRng::from_entropy(),
+        ReplayMode::Recording { replay, .. } => {
+            *rng = GameRng::from_entropy();
+            *replay = Replay::new(rng.seed());
+        }
+        ReplayMode::Playing { replay, next } => {
+            *rng = GameRng::new(replay.seed);
+            *next = 0;
+        }
+    }
+}
+
+/// Replaces the actions of the player with the ones of the replay and
+/// hands the controls back once it's over.
+pub fn play_replay(
+    mut mode: ResMut<ReplayMode>,
+    clock: Res<ReplayClock>,
+    mut actions: ResMut<Events<ActionEvent<TopDownAction>>>,
+    mut left_stick: ResMut<LeftStick>,
+) {
+    let ReplayMode::Playing { replay, next } = mode.as_mut() else {
+        return;
+    };
+    if clock.0 >= replay.frames {
+        bevy::log::info!("Replay finished");
+        *mode = ReplayMode::Off;
+        return;
+    }
+    actions.clear();
+    while let Some((frame, action)) = replay.actions.get(*next) {
+        if *frame > clock.0 {
+            break;
+        }
+        actions.send(ActionEvent {
+            action: action.clone(),
+        });
+        *next += 1;
+    }
+    left_stick.0 = replay.stick(clock.0);
+}
+
+pub fn record_actions(
+    mut mode: ResMut<ReplayMode>,
+    mut clock: ResMut<ReplayClock>,
+    mut actions: EventReader<ActionEvent<TopDownAction>>,
+    left_stick: Res<LeftStick>,
+) {
+    if let ReplayMode::Recording { replay, .. } = mode.as_mut() {
+        replay
+            .actions
+            .extend(actions.read().map(|event| (clock.0, event.action.clone())));
+        // Only changes are kept, the stick mostly rests.
+        if replay.stick(clock.0) != left_stick.0 {
+            replay.sticks.push((clock.0, left_stick.0));
+        }
+        replay.frames = clock.0 + 1;
+    }
+    clock.0 += 1;
+}
+
+fn write_recording(mode: &ReplayMode) {
+    let ReplayMode::Recording {
+        path: Some(path),
+        replay,
+    } = mode
+    else {
+        return;
+    };
+    match std::fs::write(path, replay.to_string()) {
+        Ok(()) => bevy::log::info!("Replay written to {}", path.display()),
+        Err(err) => bevy::log::error!("Could not write replay: {}", err),
+    }
+}
+
+pub fn save_recording(mode: Res<ReplayMode>) {
+    write_recording(&mode);
+}
+
+/// Also keeps the run if the game is closed in the middle of it.
+pub fn save_recording_on_exit(mode: Res<ReplayMode>, mut exit_events: EventReader<AppExit>) {
+    if exit_events.read().count() > 0 {
+        write_recording(&mode);
+    }
+}
+
+/// Replays skip the main menu.
+pub fn start_replay(mode: Res<ReplayMode>, mut game_state: ResMut<NextState<GameState>>) {
+    if mode.is_playing() {
+        game_state.set(GameState::InGame);
+    }
+}
+
+pub struct ReplayPlugin;
+impl Plugin for ReplayPlugin {
+    fn build(&self, app: &mut App) {
+        let replaying = !matches!(
+            *app.world.get_resource_or_insert_with(ReplayMode::default),
+            ReplayMode::Off
+        );
+        if replaying {
+            // Variable frame times would make every run play out differently.
+            app.insert_resource(TimeUpdateStrategy::ManualDuration(FRAME_TIME));
+            if let Some(mut rapier_configuration) =
+                app.world.get_resource_mut::<RapierConfiguration>()
+            {
+                rapier_configuration.timestep_mode = TimestepMode::Fixed {
+                    dt: FRAME_TIME.as_secs_f32(),
+                    substeps: 1,
+                };
+            }
+        }
+        app.init_resource::<GameRng>()
+            .init_resource::<ReplayClock>()
+            .add_event::<ActionEvent<TopDownAction>>()
+            .add_systems(OnEnter(GameState::InGame), start_run)
+            .add_systems(OnExit(GameState::InGame), save_recording)
+            .add_systems(OnEnter(GameState::MainMenu), start_replay)
+            .add_systems(
+                Update,
+                play_replay
+                    .after(input::input_mapping_system::<TopDownAction>)
+                    .after(keymap::gamepad_actions)
+                    .after(keymap::read_left_stick)
+                    .run_if(in_state(GameState::InGame).and_then(in_state(PauseState::Running))),
+            )
+            .add_systems(
+                Last,
+                (
+                    record_actions.run_if(
+                        in_state(GameState::InGame).and_then(in_state(PauseState::Running)),
+                    ),
+                    save_recording_on_exit.run_if(in_state(GameState::InGame)),
+                ),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::TestGame;
+
+    fn run(game: &mut TestGame) -> Vec3 {
+        for frame in 0..90 {
+            match frame {
+                0..=29 => game.send_action(TopDownAction::MoveUp),
+                30..=59 => {
+                    game.send_action(TopDownAction::MoveRight);
+                    game.send_action(TopDownAction::Action);
+                }
+                _ => {}
+            }
+            game.step(1);
+        }
+        let player = game.player();
+        game.app.world.get::<Transform>(player).unwrap().translation
+    }
+
+    #[test]
+    fn replay_file_round_trip() {
+        let mut replay = Replay::new(7);
+        replay.frames = 20;
+        replay.actions = vec![(3, TopDownAction::MoveUp), (3, TopDownAction::Action)];
+        replay.sticks = vec![(4, Vec2::new(-0.3, 0.61)), (9, Vec2::ZERO)];
+        let parsed = Replay::parse(&replay.to_string()).unwrap();
+        assert!(parsed == replay);
+        assert_eq!(parsed.stick(3), Vec2::ZERO);
+        assert_eq!(parsed.stick(8), Vec2::new(-0.3, 0.61));
+        assert_eq!(parsed.stick(9), Vec2::ZERO);
+        assert!(matches!(
+            Replay::parse("seed = 1\nframes = 2\n---\n1 stick 0.5"),
+            Err(ReplayError::InvalidLine(4))
+        ));
+        assert!(matches!(
+            Replay::parse("seed = 1\n---\n1 move_up"),
+            Err(ReplayError::MissingField("frames"))
+        ));
+        assert!(matches!(
+            Replay::parse("seed = 1\nframes = 2\n---\n1 jump"),
+            Err(ReplayError::UnknownAction { line: 4, .. })
+        ));
+    }
+
+    #[test]
+    fn replay_reproduces_the_run() {
+        let mut recorded_game = TestGame::with_setup(|app| {
+            app.insert_resource(ReplayMode::Recording {
+                path: None,
+                replay: Replay::new(0),
+            });
+        });
+        let recorded_position = run(&mut recorded_game);
+        let ReplayMode::Recording { replay, .. } = recorded_game.app.world.resource::<ReplayMode>()
+        else {
+            panic!("not recording anymore");
+        };
+        let replay = replay.clone();
+        assert_eq!(
+            replay.seed,
+            recorded_game.app.world.resource::<GameRng>().seed()
+        );
+        assert!(replay.actions.len() >= 60);
+
+        let mut replayed_game = TestGame::with_setup(|app| {
+            app.insert_resource(ReplayMode::Playing { replay, next: 0 });
+        });
+        // Live input is ignored while the replay runs.
+        for _ in 0..90 {
+            replayed_game.send_action(TopDownAction::MoveLeft);
+            replayed_game.step(1);
+        }
+        let player = replayed_game.player();
+        let replayed_position = replayed_game
+            .app
+            .world
+            .get::<Transform>(player)
+            .unwrap()
+            .translation;
+        assert_eq!(replayed_position, recorded_position);
+        replayed_game.step(1);
+        assert!(!replayed_game
+            .app
+            .world
+            .resource::<ReplayMode>()
+            .is_playing());
+    }
+
+    #[test]
+    fn replay_reproduces_stick_input() {
+        let mut recorded_game = TestGame::with_setup(|app| {
+            app.insert_resource(ReplayMode::Recording {
+                path: None,
+                replay: Replay::new(0),
+            });
+        });
+        let gamepad = recorded_game.connect_gamepad();
+        recorded_game.move_left_stick(gamepad, Vec2::new(0.4, 0.7));
+        recorded_game.step(30);
+        recorded_game.move_left_stick(gamepad, Vec2::new(-0.8, 0.1));
+        recorded_game.step(30);
+        recorded_game.move_left_stick(gamepad, Vec2::ZERO);
+        recorded_game.step(30);
+        let player = recorded_game.player();
+        let recorded_position = recorded_game
+            .app
+            .world
+            .get::<Transform>(player)
+            .unwrap()
+            .translation;
+        let ReplayMode::Recording { replay, .. } = recorded_game.app.world.resource::<ReplayMode>()
+        else {
+            panic!("not recording anymore");
+        };
+        let replay = replay.clone();
+        assert_eq!(replay.sticks.len(), 3);
+        assert!(replay.actions.is_empty());
+
+        let mut replayed_game = TestGame::with_setup(|app| {
+            app.insert_resource(ReplayMode::Playing { replay, next: 0 });
+        });
+        // The live stick is ignored while the replay runs.
+        let gamepad = replayed_game.connect_gamepad();
+        replayed_game.move_left_stick(gamepad, Vec2::new(0.0, -1.0));
+        replayed_game.step(90);
+        let player = replayed_game.player();
+        let replayed_position = replayed_game
+            .app
+            .world
+            .get::<Transform>(player)
+            .unwrap()
+            .translation;
+        assert_eq!(replayed_position, recorded_position);
+    }
+}
@@ -0,0 +1,240 @@
+#![allow(clippy::type_complexity)]
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use some_bevy_tools::{
+    camera_2d::{Camera2DController, Camera2DMode},
+    collision_detection::{self, CollisionEventStart},
+    despawn,
+    health::{DeathEvent, Health},
+};
+
+use crate::{menu::PauseState, ship::Player, GameState, InGameState};
+
+/// How often the player can die before the game is over.
+pub const PLAYER_LIVES: u32 = 3;
+
+/// Health of the player in a new run.
+pub const PLAYER_HEALTH: f32 = 100.0;
+
+/// Seconds between the death of the player and the respawn.
+pub const RESPAWN_DELAY: f32 = 1.5;
+
+/// Seconds an explosion is visible.
+pub const EXPLOSION_DURATION: f32 = 0.5;
+
+/// Sets the respawn point of the player once it flies through.
+#[derive(Component, Default)]
+pub struct Checkpoint;
+
+/// Marks the dead player until it respawns at the last checkpoint.
+#[derive(Component)]
+pub struct Respawning(pub Timer);
+
+#[derive(Component)]
+pub struct Explosion(pub Timer);
+
+pub fn activate_checkpoint(
+    mut checkpoint_events: EventReader<CollisionEventStart<Player, Checkpoint>>,
+    checkpoint_query: Query<&Transform, With<Checkpoint>>,
+    mut in_game_state: ResMut<InGameState>,
+) {
+    for CollisionEventStart(_, checkpoint, _) in checkpoint_events.read() {
+        if let Ok(transform) = checkpoint_query.get(*checkpoint) {
+            in_game_state.checkpoint = transform.translation.xy();
+        }
+    }
+}
+
+/// Blows up the player and either starts the respawn or ends the game if
+/// there are no lives left.
+pub fn player_death(
+    mut commands: Commands,
+    mut death_events: EventReader<DeathEvent>,
+    mut player_query: Query<
+        (&Transform, &mut Velocity, &mut Visibility, &Health),
+        (With<Player>, Without<Respawning>),
+    >,
+    mut in_game_state: ResMut<InGameState>,
+    mut next_state: ResMut<NextState<GameState>>,
+) {
+    for event in death_events.read() {
+        let Ok((transform, mut velocity, mut visibility, health)) =
+            player_query.get_mut(event.entity)
+        else {
+            continue;
+        };
+        // Death events are sent until they're read, so one can still arrive
+        // right after the respawn.
+        if health.get() > health.get_start() {
+            continue;
+        }
+        bevy::log::info!("Player died");
+        spawn_explosion(&mut commands, transform.translation, 50.0);
+        *velocity = Velocity::zero();
+        *visibility = Visibility::Hidden;
+        commands.entity(event.entity).insert((
+            Respawning(Timer::from_seconds(RESPAWN_DELAY, TimerMode::Once)),
+            RigidBodyDisabled,
+            ColliderDisabled,
+        ));
+
+        in_game_state.lives = in_game_state.lives.saturating_sub(1);
+        if in_game_state.lives == 0 {
+            next_state.set(GameState::GameOver);
+        }
+    }
+}
+
+pub fn respawn_player(
+    mut commands: Commands,
+    time: Res<Time>,
+    in_game_state: Res<InGameState>,
+    mut player_query: Query<
+        (
+            Entity,
+            &mut Respawning,
+            &mut Transform,
+            &mut Velocity,
+            &mut Health,
+            &mut Visibility,
+        ),
+        With<Player>,
+    >,
+    mut camera_query: Query<&mut Camera2DController>,
+) {
+    for (player, mut respawning, mut transform, mut velocity, mut health, mut visibility) in
+        player_query.iter_mut()
+    {
+        if !respawning.0.tick(time.delta()).just_finished() {
+            continue;
+        }
+        bevy::log::info!("Player respawns at {}", in_game_state.checkpoint);
+        transform.translation = in_game_state.checkpoint.extend(transform.translation.z);
+        *velocity = Velocity::zero();
+        let full_health = health.get_end();
+        health.set(full_health);
+        *visibility = Visibility::Inherited;
+        commands
+            .entity(player)
+            .remove::<(Respawning, RigidBodyDisabled, ColliderDisabled)>();
+
+        for mut camera_controller in camera_query.iter_mut() {
+            camera_controller.target_entity = player;
+            camera_controller.mode = Camera2DMode::Follow;
+        }
+    }
+}
+
+/// Spawns an explosion which starts with the given size.
+pub fn spawn_explosion(commands: &mut Commands, position: Vec3, size: f32) {
+    commands.spawn((
+        SpriteBundle {
+            transform: Transform::from_translation(position),
+            sprite: Sprite {
+                color: Color::ORANGE,
+                custom_size: Some(Vec2::splat(size)),
+                ..default()
+            },
+            ..default()
+        },
+        Explosion(Timer::from_seconds(EXPLOSION_DURATION, TimerMode::Once)),
+        despawn::Cleanup(GameState::InGame),
+    ));
+}
+
+/// Grows and fades out explosions until they're gone.
+pub fn animate_explosions(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut query: Query<(Entity, &mut Explosion, &mut Transform, &mut Sprite)>,
+) {
+    for (entity, mut explosion, mut transform, mut sprite) in query.iter_mut() {
+        explosion.0.tick(time.delta());
+        let progress = explosion.0.fraction();
+        transform.scale = Vec3::splat(1.0 + progress * 2.0);
+        sprite.color.set_a(1.0 - progress);
+        if explosion.0.finished() {
+            commands.entity(entity).despawn_recursive();
+        }
+    }
+}
+
+pub struct RespawnPlugin;
+impl Plugin for RespawnPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(collision_detection::CollisionDetectionPlugin::<
+            Player,
+            Checkpoint,
+        >::default())
+            .add_systems(
+                Update,
+                (
+                    activate_checkpoint,
+                    player_death,
+                    respawn_player,
+                    animate_explosions,
+                )
+                    .run_if(in_state(GameState::InGame).and_then(in_state(PauseState::Running))),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_builder::TileType;
+    use crate::test_support::{TestGame, OPEN_SPACE};
+
+    fn kill_player(game: &mut TestGame) {
+        let player = game.player();
+        game.app
+            .world
+            .get_mut::<Health>(player)
+            .unwrap()
+            .modify(-1000.0);
+    }
+
+    #[test]
+    fn player_respawns_at_last_checkpoint() {
+        let mut game = TestGame::new();
+        game.spawn_map("C", |c| (c == 'C').then_some(TileType::Checkpoint));
+        game.teleport_player(OPEN_SPACE);
+        game.step(5);
+        assert_eq!(
+            game.app.world.resource::<InGameState>().checkpoint,
+            OPEN_SPACE
+        );
+
+        game.teleport_player(OPEN_SPACE + Vec2::new(0.0, 1000.0));
+        game.step(1);
+        kill_player(&mut game);
+        game.step(5);
+        let player = game.player();
+        assert!(game.app.world.get::<Respawning>(player).is_some());
+        assert_eq!(
+            game.app.world.resource::<InGameState>().lives,
+            PLAYER_LIVES - 1
+        );
+
+        game.step((RESPAWN_DELAY * 60.0) as usize);
+        let player = game.app.world.entity(player);
+        assert!(player.get::<Respawning>().is_none());
+        assert_eq!(
+            player.get::<Transform>().unwrap().translation.xy(),
+            OPEN_SPACE
+        );
+        let health = player.get::<Health>().unwrap();
+        assert_eq!(health.get(), health.get_end());
+        assert_eq!(game.state::<GameState>(), GameState::InGame);
+    }
+
+    #[test]
+    fn game_over_without_lives() {
+        let mut game = TestGame::new();
+        game.app.world.resource_mut::<InGameState>().lives = 1;
+        kill_player(&mut game);
+        game.step(5);
+        assert_eq!(game.state::<GameState>(), GameState::GameOver);
+    }
+}
@@ -0,0 +1,408 @@
+#![allow(clippy::type_complexity)]
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+use bevy::prelude::*;
+use some_bevy_tools::health::Health;
+use thiserror::Error;
+
+use crate::{
+    maps::{Level, LevelComplete},
+    menu::PauseState,
+    replay,
+    respawn::{Respawning, PLAYER_HEALTH},
+    ship::Player,
+    storage::{Storage, StorageError},
+    GameState, InGameState,
+};
+
+/// Name of the file, or the `localStorage` entry in the browser, the game is
+/// saved in.
+pub const SAVE_FILE: &str = "savegame.cfg";
+
+type Fields = BTreeMap<String, String>;
+type Migration = fn(&mut Fields);
+
+/// Turns the fields of a save of version `index + 1` into the ones of the
+/// next version.  Append one whenever the format changes, so older saves can
+/// still be loaded.
+const MIGRATIONS: &[Migration] = &[];
+
+/// Version of the saves written by this build.
+pub const SAVE_VERSION: u32 = MIGRATIONS.len() as u32 + 1;
+
+#[derive(Error, Debug)]
+pub enum SaveError {
+    #[error("line {0}: expected `key = value`")]
+    InvalidLine(usize),
+
+    #[error("field `{0}` is missing")]
+    MissingField(&'static str),
+
+    #[error("invalid value `{value}` for `{key}`")]
+    InvalidValue { key: &'static str, value: String },
+
+    #[error("version {0} is newer than the game")]
+    TooNew(u32),
+
+    #[error("could not store the save game: {0}")]
+    Storage(#[from] StorageError),
+}
+
+fn field<'a>(fields: &'a Fields, key: &'static str) -> Result<&'a str, SaveError> {
+    fields
+        .get(key)
+        .map(String::as_str)
+        .ok_or(SaveError::MissingField(key))
+}
+
+fn parse_field<T: FromStr>(fields: &Fields, key: &'static str) -> Result<T, SaveError> {
+    let value = field(fields, key)?;
+    value.parse().map_err(|_| SaveError::InvalidValue {
+        key,
+        value: value.to_string(),
+    })
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct Settings {
+    /// Start at the saved checkpoint instead of the tutorial, once the
+    /// tutorial is completed.
+    pub skip_tutorial: bool,
+}
+
+/// The progress of the player, stored as `key = value` lines:
+///
+/// ```text
+/// version = 1
+/// level = level_1
+/// checkpoint = 120, -40
+/// health = 80
+/// completed = tutorial
+/// skip_tutorial = true
+/// ```
+#[derive(Resource, Clone, PartialEq, Debug)]
+pub struct SaveGame {
+    pub completed: BTreeSet<Level>,
+    pub level: Level,
+
+    /// Position of the last checkpoint relative to the center of the level.
+    pub checkpoint: Vec2,
+    pub health: f32,
+    pub settings: Settings,
+}
+
+impl Default for SaveGame {
+    fn default() -> Self {
+        Self {
+            completed: BTreeSet::new(),
+            level: Level::default(),
+            checkpoint: Vec2::ZERO,
+            health: PLAYER_HEALTH,
+            settings: Settings::default(),
+        }
+    }
+}
+
+impl SaveGame {
+    /// Whether a new run starts at the saved checkpoint.
+    pub fn skips_tutorial(&self) -> bool {
+        self.settings.skip_tutorial && self.completed.contains(&Level::Tutorial)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, SaveError> {
+        Self::parse_with_migrations(source, MIGRATIONS)
+    }
+
+    fn parse_with_migrations(source: &str, migrations: &[Migration]) -> Result<Self, SaveError> {
+        let mut fields = Fields::new();
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .ok_or(SaveError::InvalidLine(index + 1))?;
+            fields.insert(key.trim().to_string(), value.trim().to_string());
+        }
+
+        let version = parse_field::<u32>(&fields, "version")?;
+        if version == 0 {
+            return Err(SaveError::InvalidValue {
+                key: "version",
+                value: version.to_string(),
+            });
+        }
+        let outdated = migrations
+            .get(version as usize - 1..)
+            .ok_or(SaveError::TooNew(version))?;
+        for migrate in outdated {
+            migrate(&mut fields);
+        }
+
+        let checkpoint = field(&fields, "checkpoint")?;
+        let invalid_checkpoint = || SaveError::InvalidValue {
+            key: "checkpoint",
+            value: checkpoint.to_string(),
+        };
+        let (x, y) = checkpoint.split_once(',').ok_or_else(invalid_checkpoint)?;
+        let checkpoint = Vec2::new(
+            x.trim().parse().map_err(|_| invalid_checkpoint())?,
+            y.trim().parse().map_err(|_| invalid_checkpoint())?,
+        );
+        let completed = field(&fields, "completed")?;
+        let completed = completed
+            .split(',')
+            .map(str::trim)
+            .filter(|level| !level.is_empty())
+            .map(|level| {
+                level.parse().map_err(|_| SaveError::InvalidValue {
+                    key: "completed",
+                    value: completed.to_string(),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        // The player would start dead or not at all.
+        let health = parse_field::<f32>(&fields, "health")?;
+        if !health.is_finite() || health <= 0.0 {
+            return Err(SaveError::InvalidValue {
+                key: "health",
+                value: field(&fields, "health")?.to_string(),
+            });
+        }
+        Ok(Self {
+            completed,
+            level: parse_field(&fields, "level")?,
+            checkpoint,
+            health,
+            settings: Settings {
+                skip_tutorial: parse_field(&fields, "skip_tutorial")?,
+            },
+        })
+    }
+}
+
+impl fmt::Display for SaveGame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let completed = self
+            .completed
+            .iter()
+            .map(Level::to_string)
+            .collect::<Vec<_>>();
+        writeln!(f, "# Save game, written by the game.")?;
+        writeln!(f, "version = {}", SAVE_VERSION)?;
+        writeln!(f, "level = {}", self.level)?;
+        writeln!(
+            f,
+            "checkpoint = {}, {}",
+            self.checkpoint.x, self.checkpoint.y
+        )?;
+        writeln!(f, "health = {}", self.health)?;
+        writeln!(f, "completed = {}", completed.join(", "))?;
+        writeln!(f, "skip_tutorial = {}", self.settings.skip_tutorial)
+    }
+}
+
+/// Where the game is saved.
+#[derive(Resource)]
+pub struct SaveStorage(pub Storage);
+
+impl Default for SaveStorage {
+    fn default() -> Self {
+        Self(Storage::platform_default(SAVE_FILE))
+    }
+}
+
+impl SaveStorage {
+    pub fn read(&self) -> Result<Option<SaveGame>, SaveError> {
+        self.0
+            .read()?
+            .map(|source| SaveGame::parse(&source))
+            .transpose()
+    }
+
+    pub fn write(&mut self, save_game: &SaveGame) -> Result<(), SaveError> {
+        Ok(self.0.write(&save_game.to_string())?)
+    }
+}
+
+/// Writes the save game and logs what went wrong.
+pub fn store_save_game(save_game: &SaveGame, storage: &mut SaveStorage) {
+    if let Err(err) = storage.write(save_game) {
+        bevy::log::warn!("Could not save the game: {}", err);
+    }
+}
+
+/// Starts without progress if the save game can't be read.
+pub fn load_save_game(mut save_game: ResMut<SaveGame>, storage: Res<SaveStorage>) {
+    match storage.read() {
+        Ok(Some(stored)) => *save_game = stored,
+        Ok(None) => {}
+        Err(err) => bevy::log::warn!("Starting without a save game: {}", err),
+    }
+}
+
+pub fn complete_levels(
+    mut completed_events: EventReader<LevelComplete>,
+    mut save_game: ResMut<SaveGame>,
+    mut storage: ResMut<SaveStorage>,
+) {
+    for LevelComplete(level) in completed_events.read() {
+        bevy::log::info!("Completed {}", level);
+        save_game.completed.insert(*level);
+        store_save_game(&save_game, &mut storage);
+    }
+}
+
+/// Saves once the player reaches a checkpoint or another level.
+pub fn record_progress(
+    in_game_state: Res<InGameState>,
+    mut save_game: ResMut<SaveGame>,
+    mut storage: ResMut<SaveStorage>,
+) {
+    let checkpoint = in_game_state.checkpoint - in_game_state.map_origin;
+    if save_game.level == in_game_state.level && save_game.checkpoint == checkpoint {
+        return;
+    }
+    save_game.level = in_game_state.level;
+    save_game.checkpoint = checkpoint;
+    store_save_game(&save_game, &mut storage);
+}
+
+/// Keeps the health of the player up to date, it's written with the next
+/// save.
+pub fn track_player_health(
+    player_query: Query<&Health, (With<Player>, Changed<Health>, Without<Respawning>)>,
+    mut save_game: ResMut<SaveGame>,
+) {
+    for health in player_query.iter() {
+        // Dead players come back with full health.
+        save_game.health = if health.get() > 0.0 {
+            health.get()
+        } else {
+            health.get_end()
+        };
+    }
+}
+
+pub fn save_on_leave(save_game: Res<SaveGame>, mut storage: ResMut<SaveStorage>) {
+    store_save_game(&save_game, &mut storage);
+}
+
+pub struct SavePlugin;
+impl Plugin for SavePlugin {
+    fn build(&self, app: &mut App) {
+        if !app.world.contains_resource::<SaveStorage>() {
+            app.init_resource::<SaveStorage>();
+        }
+        app.init_resource::<SaveGame>()
+            .add_systems(Startup, load_save_game)
+            .add_systems(
+                OnExit(GameState::InGame),
+                save_on_leave.run_if(replay::is_off),
+            )
+            .add_systems(
+                Update,
+                (
+                    record_progress.run_if(resource_changed::<InGameState>),
+                    track_player_health,
+                )
+                    .run_if(
+                        in_state(GameState::InGame)
+                            .and_then(in_state(PauseState::Running))
+                            .and_then(replay::is_off),
+                    ),
+            )
+            // Finished levels open the results screen, which pauses the game.
+            .add_systems(
+                Update,
+                complete_levels.run_if(in_state(GameState::InGame).and_then(replay::is_off)),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_builder::TileType;
+    use crate::test_support::{TestGame, OPEN_SPACE};
+
+    const LEVEL_1_SAVE: &str = "version = 1
+level = level_1
+checkpoint = 100, 0
+health = 40
+completed = tutorial
+skip_tutorial = true
+";
+
+    fn stored(game: &TestGame) -> SaveGame {
+        game.app
+            .world
+            .resource::<SaveStorage>()
+            .read()
+            .unwrap()
+            .expect("nothing saved")
+    }
+
+    #[test]
+    fn save_file_round_trip_and_migration() {
+        let save_game = SaveGame::parse(LEVEL_1_SAVE).unwrap();
+        assert_eq!(save_game.level, Level::Level1);
+        assert_eq!(save_game.checkpoint, Vec2::new(100.0, 0.0));
+        assert!(save_game.skips_tutorial());
+        assert_eq!(SaveGame::parse(&save_game.to_string()).unwrap(), save_game);
+
+        // A version 1 save which is migrated to a version 2 without health.
+        fn drop_health(fields: &mut Fields) {
+            fields.remove("health");
+        }
+        let migrated = SaveGame::parse_with_migrations(LEVEL_1_SAVE, &[drop_health as Migration]);
+        assert!(matches!(migrated, Err(SaveError::MissingField("health"))));
+        assert!(matches!(
+            SaveGame::parse(&LEVEL_1_SAVE.replace("version = 1", "version = 2")),
+            Err(SaveError::TooNew(2))
+        ));
+        for health in ["0", "-5", "NaN", "inf"] {
+            assert!(matches!(
+                SaveGame::parse(
+                    &LEVEL_1_SAVE.replace("health = 40", &format!("health = {}", health))
+                ),
+                Err(SaveError::InvalidValue { key: "health", .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn resume_at_saved_checkpoint() {
+        let mut game = TestGame::with_setup(|app| {
+            app.insert_resource(SaveStorage(Storage::Memory(Some(LEVEL_1_SAVE.to_string()))));
+        });
+        let player = game.player();
+        let transform = game.app.world.get::<Transform>(player).unwrap();
+        assert_eq!(transform.translation.xy(), Vec2::new(100.0, 0.0));
+        assert_eq!(game.app.world.get::<Health>(player).unwrap().get(), 40.0);
+        assert_eq!(
+            game.app.world.resource::<InGameState>().level,
+            Level::Level1
+        );
+
+        // Reaching a checkpoint saves it.
+        game.spawn_map("C", |c| (c == 'C').then_some(TileType::Checkpoint));
+        game.teleport_player(OPEN_SPACE);
+        game.step(5);
+        let save_game = stored(&game);
+        assert_eq!(save_game.checkpoint, OPEN_SPACE);
+        assert_eq!(save_game.health, 40.0);
+    }
+
+    #[test]
+    fn finishing_the_tutorial_is_saved() {
+        let mut game = TestGame::new();
+        game.app.world.send_event(LevelComplete(Level::Tutorial));
+        game.step(1);
+        assert_eq!(stored(&game).completed, BTreeSet::from([Level::Tutorial]));
+    }
+}
@@ -0,0 +1,685 @@
+#![allow(clippy::type_complexity)]
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy_rapier2d::dynamics::Velocity;
+use some_bevy_tools::{
+    audio_loop::AudioLoopEvent,
+    camera_2d::{Camera2DController, Camera2DMode},
+    collision_detection::CollisionEventStart,
+    despawn,
+};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::{
+    assets,
+    encounter::EncounterEvent,
+    error_handler::{log_unless_critical, GameError},
+    map_asset::MapAsset,
+    map_builder::{SpawnedMap, TileMarker, TriggerAction, UnloadMapExt},
+    maps::{self, Level, LevelComplete},
+    ship::{Direction, Player, Ship},
+    stars, GameState, InGameState, Logo,
+};
+
+#[derive(Error, Debug)]
+pub enum ScriptError {
+    #[error("line {0}: step outside of a `script <name>` block")]
+    StepOutsideScript(usize),
+
+    #[error("line {line}: script `{name}` is defined twice")]
+    DuplicateScript { line: usize, name: String },
+
+    #[error("line {line}: invalid step `{step}`: {source}")]
+    InvalidStep {
+        line: usize,
+        step: String,
+        source: StepError,
+    },
+}
+
+#[derive(Error, Clone, PartialEq, Debug)]
+pub enum StepError {
+    #[error("the step is empty")]
+    Empty,
+
+    #[error("unknown step `{0}`")]
+    UnknownStep(String),
+
+    #[error("`{name}` takes {expected} arguments, not {found}")]
+    ArgumentCount {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+
+    #[error("`{0}` is not a number")]
+    InvalidNumber(String),
+
+    #[error(transparent)]
+    UnknownLevel(#[from] maps::UnknownLevel),
+}
+
+/// One step of a level script.
+///
+/// Steps are written one per line, the name followed by its arguments:
+///
+/// ```text
+/// wait 2
+/// stars 10000 2000
+/// lock_controls
+/// unlock_controls
+/// ship_heading 1 0
+/// ship_velocity 300 0
+/// camera_move 310
+/// camera_follow
+/// show_overlay
+/// hide_overlay
+/// spawn_map level_1
+/// complete_level
+/// finish_run
+/// music_loop_offset 19.2
+/// music_start 19.2
+/// music_end 76.8
+/// ```
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ScriptStep {
+    /// Seconds until the next step runs.
+    Wait(f32),
+    Stars {
+        speed: f32,
+        acceleration: f32,
+    },
+    LockControls,
+    UnlockControls,
+    ShipHeading(Vec2),
+    ShipVelocity(Vec2),
+    /// Moves the camera on its own with the given speed.
+    CameraMove(f32),
+    CameraFollow,
+    ShowOverlay,
+    HideOverlay,
+    /// Replaces the active map with the level, centered at the player.
+    SpawnMap(Level),
+    /// Finishes the current level, which starts the transition to the next
+    /// one.
+    CompleteLevel,
+    /// Ends the run and goes back to the main menu.
+    FinishRun,
+    MusicLoopOffset(f32),
+    MusicStart(f32),
+    MusicEnd(f32),
+}
+
+impl FromStr for ScriptStep {
+    type Err = StepError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split_whitespace();
+        let name = parts.next().ok_or(StepError::Empty)?;
+        let arguments: Vec<&str> = parts.collect();
+        let argument_count = |expected: usize| {
+            if arguments.len() == expected {
+                Ok(())
+            } else {
+                Err(StepError::ArgumentCount {
+                    name: name.to_string(),
+                    expected,
+                    found: arguments.len(),
+                })
+            }
+        };
+        let numbers = |count: usize| -> Result<Vec<f32>, StepError> {
+            argument_count(count)?;
+            arguments
+                .iter()
+                .map(|argument| {
+                    argument
+                        .parse::<f32>()
+                        .map_err(|_| StepError::InvalidNumber(argument.to_string()))
+                })
+                .collect()
+        };
+        Ok(match name {
+            "wait" => ScriptStep::Wait(numbers(1)?[0]),
+            "stars" => {
+                let numbers = numbers(2)?;
+                ScriptStep::Stars {
+                    speed: numbers[0],
+                    acceleration: numbers[1],
+                }
+            }
+            "lock_controls" => {
+                numbers(0)?;
+                ScriptStep::LockControls
+            }
+            "unlock_controls" => {
+                numbers(0)?;
+                ScriptStep::UnlockControls
+            }
+            "ship_heading" => {
+                let numbers = numbers(2)?;
+                ScriptStep::ShipHeading(Vec2::new(numbers[0], numbers[1]))
+            }
+            "ship_velocity" => {
+                let numbers = numbers(2)?;
+                ScriptStep::ShipVelocity(Vec2::new(numbers[0], numbers[1]))
+            }
+            "camera_move" => ScriptStep::CameraMove(numbers(1)?[0]),
+            "camera_follow" => {
+                numbers(0)?;
+                ScriptStep::CameraFollow
+            }
+            "show_overlay" => {
+                numbers(0)?;
+                ScriptStep::ShowOverlay
+            }
+            "hide_overlay" => {
+                numbers(0)?;
+                ScriptStep::HideOverlay
+            }
+            "spawn_map" => {
+                argument_count(1)?;
+                ScriptStep::SpawnMap(arguments[0].parse()?)
+            }
+            "complete_level" => {
+                numbers(0)?;
+                ScriptStep::CompleteLevel
+            }
+            "finish_run" => {
+                numbers(0)?;
+                ScriptStep::FinishRun
+            }
+            "music_loop_offset" => ScriptStep::MusicLoopOffset(numbers(1)?[0]),
+            "music_start" => ScriptStep::MusicStart(numbers(1)?[0]),
+            "music_end" => ScriptStep::MusicEnd(numbers(1)?[0]),
+            _ => return Err(StepError::UnknownStep(name.to_string())),
+        })
+    }
+}
+
+impl fmt::Display for ScriptStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptStep::Wait(seconds) => write!(f, "wait {}", seconds),
+            ScriptStep::Stars {
+                speed,
+                acceleration,
+            } => write!(f, "stars {} {}", speed, acceleration),
+            ScriptStep::LockControls => write!(f, "lock_controls"),
+            ScriptStep::UnlockControls => write!(f, "unlock_controls"),
+            ScriptStep::ShipHeading(heading) => {
+                write!(f, "ship_heading {} {}", heading.x, heading.y)
+            }
+            ScriptStep::ShipVelocity(velocity) => {
+                write!(f, "ship_velocity {} {}", velocity.x, velocity.y)
+            }
+            ScriptStep::CameraMove(speed) => write!(f, "camera_move {}", speed),
+            ScriptStep::CameraFollow => write!(f, "camera_follow"),
+            ScriptStep::ShowOverlay => write!(f, "show_overlay"),
+            ScriptStep::HideOverlay => write!(f, "hide_overlay"),
+            ScriptStep::SpawnMap(level) => write!(f, "spawn_map {}", level),
+            ScriptStep::CompleteLevel => write!(f, "complete_level"),
+            ScriptStep::FinishRun => write!(f, "finish_run"),
+            ScriptStep::MusicLoopOffset(position) => write!(f, "music_loop_offset {}", position),
+            ScriptStep::MusicStart(position) => write!(f, "music_start {}", position),
+            ScriptStep::MusicEnd(position) => write!(f, "music_end {}", position),
+        }
+    }
+}
+
+/// Named lists of steps which triggers start.
+pub type Scripts = HashMap<String, Vec<ScriptStep>>;
+
+/// Script a map can have to react to the start of one of its encounters.
+pub const ENCOUNTER_STARTED_SCRIPT: &str = "EncounterStarted";
+
+/// Script a map can have to react to one of its encounters being beaten.
+pub const ENCOUNTER_COMPLETED_SCRIPT: &str = "EncounterCompleted";
+
+/// Parses a `.script` file.
+///
+/// A `script <name>` line starts a script, the steps follow one per line
+/// until the next script starts.  The name is the one of the trigger which
+/// starts the script, or [`ENCOUNTER_STARTED_SCRIPT`] and
+/// [`ENCOUNTER_COMPLETED_SCRIPT`] for the encounters of the map:
+///
+/// ```text
+/// # Comment
+/// script SimplyForward
+/// music_loop_offset 19.2
+///
+/// script DeepSpace
+/// lock_controls
+/// wait 2
+/// show_overlay
+/// ```
+pub fn parse_scripts(source: &str) -> Result<Scripts, ScriptError> {
+    let mut scripts = Scripts::new();
+    let mut current = None;
+    for (index, line) in source.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix("script ") {
+            let name = name.trim().to_string();
+            if scripts.contains_key(&name) {
+                return Err(ScriptError::DuplicateScript {
+                    line: line_number,
+                    name,
+                });
+            }
+            scripts.insert(name.clone(), Vec::new());
+            current = Some(name);
+            continue;
+        }
+        let steps = current
+            .as_ref()
+            .and_then(|name| scripts.get_mut(name))
+            .ok_or(ScriptError::StepOutsideScript(line_number))?;
+        let step = line.parse().map_err(|source| ScriptError::InvalidStep {
+            line: line_number,
+            step: line.to_string(),
+            source,
+        })?;
+        steps.push(step);
+    }
+    Ok(scripts)
+}
+
+/// A script which is currently played, despawned after its last step.
+#[derive(Component)]
+pub struct RunningScript {
+    pub name: String,
+    steps: Vec<ScriptStep>,
+    next: usize,
+    wait: Option<Timer>,
+}
+
+impl RunningScript {
+    pub fn new(name: impl Into<String>, steps: Vec<ScriptStep>) -> Self {
+        Self {
+            name: name.into(),
+            steps,
+            next: 0,
+            wait: None,
+        }
+    }
+}
+
+/// The scripts of the spawned map with the given id.
+fn map_scripts<'a>(
+    spawned_maps: &Query<&SpawnedMap>,
+    loaded_maps: &'a Assets<MapAsset>,
+    map_id: Uuid,
+) -> Option<&'a Scripts> {
+    spawned_maps
+        .iter()
+        .find(|spawned_map| spawned_map.id == map_id)
+        .and_then(|spawned_map| spawned_map.source)
+        .and_then(|source| loaded_maps.get(source))
+        .map(|map_asset| &map_asset.scripts)
+}
+
+/// Starts the script named like the action of the trigger the player flew
+/// into.  The scripts come from the map the trigger belongs to.
+pub fn start_scripts(
+    mut commands: Commands,
+    mut trigger_events: EventReader<CollisionEventStart<Player, TriggerAction>>,
+    trigger_query: Query<(&TriggerAction, &TileMarker)>,
+    spawned_maps: Query<&SpawnedMap>,
+    loaded_maps: Res<Assets<MapAsset>>,
+) -> Result<(), GameError> {
+    for CollisionEventStart(_, trigger, _) in trigger_events.read() {
+        let Ok((trigger, TileMarker(map_id))) = trigger_query.get(*trigger) else {
+            continue;
+        };
+        let name = &trigger.0;
+        bevy::log::info!("Trigger {}", name);
+        let steps =
+            map_scripts(&spawned_maps, &loaded_maps, *map_id).and_then(|scripts| scripts.get(name));
+        match steps {
+            Some(steps) => {
+                commands.spawn((
+                    RunningScript::new(name.clone(), steps.clone()),
+                    despawn::Cleanup(GameState::InGame),
+                ));
+            }
+            None => log_unless_critical(GameError::UnknownScript(name.clone()))?,
+        }
+    }
+    Ok(())
+}
+
+/// Starts the encounter scripts of the map, maps without them stay quiet.
+pub fn start_encounter_scripts(
+    mut commands: Commands,
+    mut encounter_events: EventReader<EncounterEvent>,
+    spawned_maps: Query<&SpawnedMap>,
+    loaded_maps: Res<Assets<MapAsset>>,
+) {
+    for event in encounter_events.read() {
+        let (name, map_id) = match event {
+            EncounterEvent::Started(map_id) => (ENCOUNTER_STARTED_SCRIPT, map_id),
+            EncounterEvent::Completed(map_id) => (ENCOUNTER_COMPLETED_SCRIPT, map_id),
+            EncounterEvent::WaveSpawned(..) => continue,
+        };
+        let Some(steps) =
+            map_scripts(&spawned_maps, &loaded_maps, *map_id).and_then(|scripts| scripts.get(name))
+        else {
+            continue;
+        };
+        commands.spawn((
+            RunningScript::new(name, steps.clone()),
+            despawn::Cleanup(GameState::InGame),
+        ));
+    }
+}
+
+/// Everything the steps of a script can change.
+#[derive(SystemParam)]
+pub struct ScriptTargets<'w, 's> {
+    commands: Commands<'w, 's>,
+    image_assets: Res<'w, assets::ImageAssets>,
+    map_assets: Res<'w, assets::MapAssets>,
+    music_assets: Res<'w, assets::MusicAssets>,
+    loaded_maps: Res<'w, Assets<MapAsset>>,
+    audio_events: EventWriter<'w, AudioLoopEvent>,
+    completed_events: EventWriter<'w, LevelComplete>,
+    stars_materials: ResMut<'w, stars::StarMaterialSettings>,
+    in_game_state: ResMut<'w, InGameState>,
+    next_game_state: ResMut<'w, NextState<GameState>>,
+    ship_query: Query<
+        'w,
+        's,
+        (
+            &'static mut Direction,
+            &'static mut Velocity,
+            &'static Transform,
+        ),
+        (With<Ship>, With<Player>),
+    >,
+    camera_query: Query<'w, 's, &'static mut Camera2DController>,
+    overlay_query: Query<'w, 's, &'static mut Visibility, With<Logo>>,
+}
+
+impl ScriptTargets<'_, '_> {
+    fn run(&mut self, step: ScriptStep) -> Result<(), GameError> {
+        match step {
+            ScriptStep::Wait(_) => {}
+            ScriptStep::Stars {
+                speed,
+                acceleration,
+            } => {
+                self.stars_materials.desired_speed_x = speed;
+                self.stars_materials.acceleration = acceleration;
+            }
+            ScriptStep::LockControls => self.in_game_state.block_controls = true,
+            ScriptStep::UnlockControls => self.in_game_state.block_controls = false,
+            ScriptStep::ShipHeading(heading) => {
+                let (mut direction, _, _) = self
+                    .ship_query
+                    .get_single_mut()
+                    .map_err(|_| GameError::MissingScriptTarget("player"))?;
+                direction.set(heading);
+            }
+            ScriptStep::ShipVelocity(linvel) => {
+                let (_, mut velocity, _) = self
+                    .ship_query
+                    .get_single_mut()
+                    .map_err(|_| GameError::MissingScriptTarget("player"))?;
+                velocity.linvel = linvel;
+            }
+            ScriptStep::CameraMove(speed) => {
+                let mut camera_controller = self.camera()?;
+                camera_controller.mode = Camera2DMode::Move;
+                camera_controller.speed = speed;
+            }
+            ScriptStep::CameraFollow => self.camera()?.mode = Camera2DMode::Follow,
+            ScriptStep::ShowOverlay => *self.overlay()? = Visibility::Visible,
+            ScriptStep::HideOverlay => *self.overlay()? = Visibility::Hidden,
+            ScriptStep::SpawnMap(level) => {
+                let (_, _, transform) = self
+                    .ship_query
+                    .get_single()
+                    .map_err(|_| GameError::MissingScriptTarget("player"))?;
+                let center = transform.translation.xy();
+                if let Some(previous_map) = self.in_game_state.active_map {
+                    self.commands.unload_map(previous_map);
+                }
+                let map_id = maps::spawn_level(
+                    &mut self.commands,
+                    level,
+                    &self.map_assets,
+                    &self.loaded_maps,
+                    &self.image_assets,
+                    center,
+                )?;
+                self.in_game_state.active_map = Some(map_id);
+                self.in_game_state.level = level;
+                self.in_game_state.map_origin = center;
+                self.in_game_state.checkpoint = center;
+            }
+            ScriptStep::CompleteLevel => {
+                self.completed_events
+                    .send(LevelComplete(self.in_game_state.level));
+            }
+            ScriptStep::FinishRun => self.next_game_state.set(GameState::MainMenu),
+            ScriptStep::MusicLoopOffset(position) => {
+                self.audio_events.send(AudioLoopEvent::LoopOffsetImmediate(
+                    position,
+                    self.music_assets.space.clone(),
+                ));
+            }
+            ScriptStep::MusicStart(position) => {
+                self.audio_events
+                    .send(AudioLoopEvent::StartPositionImmediate(
+                        position,
+                        self.music_assets.space.clone(),
+                    ));
+            }
+            ScriptStep::MusicEnd(position) => {
+                self.audio_events.send(AudioLoopEvent::EndPositionImmediate(
+                    position,
+                    self.music_assets.space.clone(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn camera(&mut self) -> Result<Mut<'_, Camera2DController>, GameError> {
+        self.camera_query
+            .get_single_mut()
+            .map_err(|_| GameError::MissingScriptTarget("camera"))
+    }
+
+    fn overlay(&mut self) -> Result<Mut<'_, Visibility>, GameError> {
+        self.overlay_query
+            .get_single_mut()
+            .map_err(|_| GameError::MissingScriptTarget("overlay"))
+    }
+}
+
+/// Runs the steps of the running scripts until they have to wait.
+///
+/// A step which can't run is logged and skipped so a script never gets
+/// stuck halfway, for example with locked controls.
+pub fn run_scripts(
+    mut scripts: Query<(Entity, &mut RunningScript)>,
+    mut targets: ScriptTargets,
+    time: Res<Time>,
+) -> Result<(), GameError> {
+    for (entity, mut script) in scripts.iter_mut() {
+        if let Some(wait) = script.wait.as_mut() {
+            if !wait.tick(time.delta()).finished() {
+                continue;
+            }
+            script.wait = None;
+        }
+        while let Some(step) = script.steps.get(script.next).copied() {
+            script.next += 1;
+            if let Err(err) = targets.run(step) {
+                log_unless_critical(err)?;
+            }
+            if let ScriptStep::Wait(seconds) = step {
+                script.wait = Some(Timer::new(
+                    Duration::from_secs_f32(seconds.max(0.0)),
+                    TimerMode::Once,
+                ));
+                break;
+            }
+        }
+        if script.wait.is_none() && script.next >= script.steps.len() {
+            targets.commands.entity(entity).despawn();
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{enemy, menu::PauseState, test_support::TestGame};
+
+    const SCRIPTS: &str = "\
+# Comment
+script Intro
+lock_controls
+wait 1.5
+stars 10000 2000
+
+script Outro
+complete_level
+spawn_map level_1
+";
+
+    #[test]
+    fn parse_scripts_and_steps() {
+        let scripts = parse_scripts(SCRIPTS).unwrap();
+        assert_eq!(
+            scripts["Intro"],
+            [
+                ScriptStep::LockControls,
+                ScriptStep::Wait(1.5),
+                ScriptStep::Stars {
+                    speed: 10000.0,
+                    acceleration: 2000.0
+                }
+            ]
+        );
+        assert_eq!(
+            scripts["Outro"],
+            [
+                ScriptStep::CompleteLevel,
+                ScriptStep::SpawnMap(Level::Level1)
+            ]
+        );
+        for step in scripts.values().flatten() {
+            assert_eq!(step.to_string().parse::<ScriptStep>(), Ok(*step));
+        }
+
+        assert!(matches!(
+            parse_scripts(&SCRIPTS.replace("wait 1.5", "wait")),
+            Err(ScriptError::InvalidStep {
+                line: 4,
+                source: StepError::ArgumentCount {
+                    expected: 1,
+                    found: 0,
+                    ..
+                },
+                ..
+            })
+        ));
+        assert_eq!(
+            "wait soon".parse::<ScriptStep>(),
+            Err(StepError::InvalidNumber("soon".to_string()))
+        );
+        assert_eq!(
+            "spawn_map level_9".parse::<ScriptStep>(),
+            Err(StepError::UnknownLevel(maps::UnknownLevel(
+                "level_9".to_string()
+            )))
+        );
+        assert_eq!(
+            "jump".parse::<ScriptStep>(),
+            Err(StepError::UnknownStep("jump".to_string()))
+        );
+        assert!(matches!(
+            parse_scripts("lock_controls\n"),
+            Err(ScriptError::StepOutsideScript(1))
+        ));
+        assert!(matches!(
+            parse_scripts(&SCRIPTS.replace("Outro", "Intro")),
+            Err(ScriptError::DuplicateScript { line: 7, .. })
+        ));
+    }
+
+    #[test]
+    fn enemies_do_not_start_scripts() {
+        let mut game = TestGame::new();
+        // The script of this trigger would finish the tutorial.
+        let position = game.trigger_position("DeepSpace");
+        game.with_commands(|commands, image_assets| {
+            enemy::spawn_enemy(commands, image_assets, position, 30.0, 0.0);
+        });
+        game.step(10);
+        let scripts = game
+            .app
+            .world
+            .query::<&RunningScript>()
+            .iter(&game.app.world)
+            .count();
+        assert_eq!(scripts, 0);
+        assert!(!game.app.world.resource::<InGameState>().block_controls);
+        assert_eq!(game.state::<PauseState>(), PauseState::Running);
+
+        // The single trigger is still there for the player.
+        game.teleport_player(position);
+        game.step(10);
+        assert!(game.app.world.resource::<InGameState>().block_controls);
+    }
+
+    #[derive(Resource, Default)]
+    struct MusicStarts(Vec<f32>);
+
+    fn record_music_starts(
+        mut events: EventReader<AudioLoopEvent>,
+        mut starts: ResMut<MusicStarts>,
+    ) {
+        for event in events.read() {
+            if let AudioLoopEvent::StartPositionImmediate(position, _) = event {
+                starts.0.push(*position);
+            }
+        }
+    }
+
+    #[test]
+    fn encounters_start_map_scripts() {
+        let mut game = TestGame::with_setup(|app| {
+            app.insert_resource(crate::progression::StartLevel(Some(Level::Level1)));
+        });
+        game.app
+            .init_resource::<MusicStarts>()
+            .add_systems(Update, record_music_starts);
+        let map = game.app.world.resource::<InGameState>().active_map.unwrap();
+        game.app.world.send_event(EncounterEvent::Completed(map));
+        game.step(2);
+        assert_eq!(game.app.world.resource::<MusicStarts>().0, [19.2]);
+
+        // Maps without encounter scripts stay quiet.
+        game.app
+            .world
+            .send_event(EncounterEvent::Started(uuid::Uuid::new_v4()));
+        game.step(2);
+        assert_eq!(game.app.world.resource::<MusicStarts>().0, [19.2]);
+    }
+}
@@ -1,33 +1,52 @@
 #![allow(clippy::type_complexity)]
 #![allow(clippy::too_many_arguments)]
 
-use std::time::Duration;
-
-use crate::{assets, error_handler::GameError, health, maps, stars, InGameState, Logo};
+use crate::{faction::Faction, health, weapon::Weapon};
 use bevy::prelude::*;
 use bevy_rapier2d::dynamics::Velocity;
-use some_bevy_tools::{
-    audio_loop::AudioLoopEvent, collision_detection::CollisionEventStart, physics2d,
-};
-
-#[derive(Component)]
-pub enum Direction {
-    Left,
-    Right,
-    Up,
-    Down,
+use some_bevy_tools::physics2d;
+
+/// Radians per second a ship turns towards its target direction.
+pub const TURN_SPEED: f32 = 10.0;
+
+/// Where a ship points and where it turns to, both normalized.
+#[derive(Component, Clone, Copy, PartialEq, Debug)]
+pub struct Direction {
+    pub heading: Vec2,
+    pub target: Vec2,
 }
 impl Direction {
+    pub fn new(heading: Vec2) -> Self {
+        let heading = heading.try_normalize().unwrap_or(Vec2::Y);
+        Self {
+            heading,
+            target: heading,
+        }
+    }
+
     pub fn vector(&self) -> Vec2 {
-        match self {
-            Direction::Left => Vec2::new(-1.0, 0.0),
-            Direction::Right => Vec2::new(1.0, 0.0),
-            Direction::Up => Vec2::new(0.0, 1.0),
-            Direction::Down => Vec2::new(0.0, -1.0),
+        self.heading
+    }
+
+    /// Lets the ship turn towards the direction over the next frames.  A zero
+    /// vector keeps the current target.
+    pub fn turn_to(&mut self, direction: Vec2) {
+        if let Some(target) = direction.try_normalize() {
+            self.target = target;
         }
     }
+
+    /// Points the ship into the direction right away.
+    pub fn set(&mut self, direction: Vec2) {
+        *self = Self::new(direction);
+    }
 }
 
+/// Direction a ship accelerates into.  Analog input gives a length below 1
+/// for less acceleration.
+#[derive(Component, Clone, Copy, Default, Debug)]
+pub struct Thrust(pub Vec2);
+
 #[derive(Component, Default)]
 pub struct Ship;
 
@@ -39,122 +58,226 @@ pub struct ShipBundle {
     pub acceleration: physics2d::Acceleration,
 
     pub direction: Direction,
+    pub thrust: Thrust,
     pub health: health::Health,
+    pub faction: Faction,
+    pub weapon: Weapon,
     pub ship: Ship,
 }
-#[derive(Component)]
+#[derive(Component, Default)]
 pub struct Player;
 
-pub fn ship_orientation(mut query: Query<(&Direction, &mut Transform)>) {
-    for (direction, mut transform) in query.iter_mut() {
-        match direction {
-            Direction::Left => {
-                transform.rotation = Quat::from_rotation_z(std::f32::consts::PI / 2.0);
-            }
-            Direction::Right => {
-                transform.rotation = Quat::from_rotation_z(-std::f32::consts::PI / 2.0);
-            }
-            Direction::Up => {
-                transform.rotation = Quat::from_rotation_z(0.0);
-            }
-            Direction::Down => {
-                transform.rotation = Quat::from_rotation_z(std::f32::consts::PI);
-            }
-        }
+/// Turns ships towards their target direction and rotates their sprite.
+pub fn ship_orientation(time: Res<Time>, mut query: Query<(&mut Direction, &mut Transform)>) {
+    for (mut direction, mut transform) in query.iter_mut() {
+        let max_turn = TURN_SPEED * time.delta_seconds();
+        let turn = direction
+            .heading
+            .angle_between(direction.target)
+            .clamp(-max_turn, max_turn);
+        direction.heading = Vec2::from_angle(turn).rotate(direction.heading).normalize();
+        transform.rotation = Quat::from_rotation_z(Vec2::Y.angle_between(direction.heading));
     }
 }
 
-#[derive(Component, Clone, Copy, Default)]
-pub enum TutorialTrigger {
-    #[default]
-    SimplyForward,
-    TurnedRight,
-    DeepSpace,
+/// Accelerates ships along their thrust, up to the maximum speed in any
+/// direction.
+pub fn thrust_controller(
+    time: Res<Time>,
+    mut query: Query<(&mut Velocity, &physics2d::Acceleration, &Thrust)>,
+) {
+    for (mut velocity, acceleration, thrust) in query.iter_mut() {
+        velocity.linvel +=
+            thrust.0.clamp_length_max(1.0) * acceleration.amount * time.delta_seconds();
+        velocity.linvel = velocity.linvel.clamp_length_max(acceleration.max_speed);
+    }
 }
 
-pub fn tutorial_trigger_system(
-    mut commands: Commands,
-    image_assets: Res<assets::ImageAssets>,
-    mut turtorial_trigger1: EventReader<
-        some_bevy_tools::collision_detection::CollisionEventStart<Ship, TutorialTrigger>,
-    >,
-    query: Query<&TutorialTrigger>,
-    music_assets: Res<assets::MusicAssets>,
-    mut audio_events: EventWriter<AudioLoopEvent>,
-    mut stars_materials: ResMut<stars::StarMaterialSettings>,
-    mut in_game_state: ResMut<InGameState>,
-    mut ship_direction: Query<
-        (&mut Direction, &mut Velocity, &Transform),
-        (With<Ship>, With<Player>),
-    >,
-    mut camera_query: Query<&mut some_bevy_tools::camera_2d::Camera2DController>,
-    mut logo_query: Query<&mut Visibility, With<Logo>>,
-    time: Res<Time>,
-    mut logo_timer: Local<Option<Timer>>,
-    mut logo_disappear_timer: Local<Option<Timer>>,
-) -> Result<(), GameError> {
-    for CollisionEventStart(_, trigger, _) in turtorial_trigger1.read() {
-        let trigger = query.get(*trigger).unwrap();
-        match trigger {
-            TutorialTrigger::SimplyForward => {
-                bevy::log::info!("SimplyForward");
-                audio_events.send(AudioLoopEvent::LoopOffsetImmediate(
-                    19.2,
-                    music_assets.space.clone(),
-                ));
-            }
-            TutorialTrigger::TurnedRight => {
-                bevy::log::info!("TurnedRight");
-                audio_events.send_batch([
-                    AudioLoopEvent::StartPositionImmediate(19.2, music_assets.space.clone()),
-                    AudioLoopEvent::EndPositionImmediate(19.2 * 4.0, music_assets.space.clone()),
-                ]);
-            }
-            TutorialTrigger::DeepSpace => {
-                bevy::log::info!("DeepSpace");
-                stars_materials.desired_speed_x = 10000.0;
-                stars_materials.acceleration = 2000.0;
-                in_game_state.block_controls = true;
-                let (mut direction, mut velocity, _) = ship_direction.get_single_mut().unwrap();
-                *direction = Direction::Right;
-                velocity.linvel.x = 300.0;
-                velocity.linvel.y = 0.0;
-
-                let mut camera_controller = camera_query.get_single_mut().unwrap();
-                camera_controller.mode = some_bevy_tools::camera_2d::Camera2DMode::Move;
-                camera_controller.speed = 310.0;
-
-                *logo_timer = Some(Timer::new(Duration::from_secs(2), TimerMode::Once));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_builder::TriggerAction;
+    use crate::test_support::{TestGame, OPEN_SPACE};
+    use crate::{stars, InGameState};
+    use some_bevy_tools::{collision_detection::CollisionEventStart, controller_2d::TopDownAction};
+
+    #[derive(Resource, Default)]
+    struct FiredTriggers(Vec<String>);
+
+    fn record_triggers(
+        mut events: EventReader<CollisionEventStart<Player, TriggerAction>>,
+        query: Query<&TriggerAction>,
+        mut fired: ResMut<FiredTriggers>,
+    ) {
+        for CollisionEventStart(_, trigger, _) in events.read() {
+            if let Ok(trigger) = query.get(*trigger) {
+                fired.0.push(trigger.0.clone());
             }
         }
     }
-    if let Some(timer) = logo_timer.as_mut() {
-        if timer.tick(time.delta()).just_finished() {
-            let mut logo_visibility = logo_query.get_single_mut().unwrap();
-            *logo_visibility = Visibility::Visible;
-            *logo_disappear_timer = Some(Timer::new(Duration::from_secs(7), TimerMode::Once));
+
+    fn player_direction(game: &mut TestGame) -> Direction {
+        let player = game.player();
+        *game.app.world.get::<Direction>(player).unwrap()
+    }
+
+    fn player_velocity(game: &mut TestGame) -> Vec2 {
+        let player = game.player();
+        game.app.world.get::<Velocity>(player).unwrap().linvel
+    }
+
+    /// Presses the directions which bring the player to `target` without
+    /// overshooting, nothing slows it down otherwise.  Returns whether it
+    /// arrived.
+    fn steer_to(game: &mut TestGame, target: Vec2) -> bool {
+        let player = game.player();
+        let position = game
+            .app
+            .world
+            .get::<Transform>(player)
+            .unwrap()
+            .translation
+            .xy();
+        let velocity = player_velocity(game);
+        let offset = target - position;
+        if offset.length() < 5.0 && velocity.length() < 30.0 {
+            return true;
         }
+        let push = (offset * 3.0).clamp(Vec2::splat(-250.0), Vec2::splat(250.0)) - velocity;
+        if push.x > 20.0 {
+            game.send_action(TopDownAction::MoveRight);
+        } else if push.x < -20.0 {
+            game.send_action(TopDownAction::MoveLeft);
+        }
+        if push.y > 20.0 {
+            game.send_action(TopDownAction::MoveUp);
+        } else if push.y < -20.0 {
+            game.send_action(TopDownAction::MoveDown);
+        }
+        false
     }
-    if let Some(timer) = logo_disappear_timer.as_mut() {
-        if timer.tick(time.delta()).just_finished() {
-            let (_, mut velocity, transform) = ship_direction.get_single_mut().unwrap();
-            velocity.linvel.x = 0.0;
-            velocity.linvel.y = 0.0;
-
-            let mut logo_visibility = logo_query.get_single_mut().unwrap();
-            *logo_visibility = Visibility::Hidden;
-            maps::level_1::build_level_1()?.spawn_tiles(
-                &mut commands,
-                &image_assets,
-                transform.translation.xy(),
-            );
-
-            let mut camera_controller = camera_query.get_single_mut().unwrap();
-            camera_controller.mode = some_bevy_tools::camera_2d::Camera2DMode::Follow;
-            in_game_state.block_controls = false;
-            stars_materials.desired_speed_x = 0.0;
-            stars_materials.acceleration = 20000.0;
+
+    #[test]
+    fn combined_actions_move_diagonally() {
+        let mut game = TestGame::new();
+        game.teleport_player(OPEN_SPACE);
+        for _ in 0..30 {
+            game.send_action(TopDownAction::MoveUp);
+            game.send_action(TopDownAction::MoveRight);
+            game.step(1);
         }
+        let velocity = player_velocity(&mut game);
+        assert!(velocity.x > 0.0);
+        assert!((velocity.x - velocity.y).abs() < 0.001);
+        let diagonal = Vec2::ONE.normalize();
+        assert!(player_direction(&mut game)
+            .heading
+            .abs_diff_eq(diagonal, 0.001));
+    }
+
+    #[test]
+    fn ship_turns_smoothly() {
+        let mut game = TestGame::new();
+        game.teleport_player(OPEN_SPACE);
+        game.send_action(TopDownAction::MoveDown);
+        game.step(1);
+        let direction = player_direction(&mut game);
+        assert_eq!(direction.target, Vec2::NEG_Y);
+        let turned = Vec2::Y.angle_between(direction.heading).abs();
+        assert!(turned > 0.0 && turned <= TURN_SPEED / 60.0 + 0.001);
+
+        game.step(30);
+        assert!(player_direction(&mut game)
+            .heading
+            .abs_diff_eq(Vec2::NEG_Y, 0.001));
+    }
+
+    #[test]
+    fn left_stick_steers_at_any_angle() {
+        let mut game = TestGame::new();
+        game.teleport_player(OPEN_SPACE);
+        let gamepad = game.connect_gamepad();
+        let stick = Vec2::new(-0.3, 0.6);
+        game.move_left_stick(gamepad, stick);
+        game.step(30);
+        let player = game.player();
+        assert_eq!(game.app.world.get::<Thrust>(player).unwrap().0, stick);
+        assert!(player_velocity(&mut game)
+            .normalize()
+            .abs_diff_eq(stick.normalize(), 0.001));
+        assert!(player_direction(&mut game)
+            .heading
+            .abs_diff_eq(stick.normalize(), 0.001));
+    }
+
+    #[test]
+    fn bullets_fly_along_the_heading() {
+        let mut game = TestGame::new();
+        game.teleport_player(OPEN_SPACE);
+        let player = game.player();
+        let heading = Vec2::new(1.0, -2.0).normalize();
+        game.app
+            .world
+            .get_mut::<Direction>(player)
+            .unwrap()
+            .set(heading);
+        game.send_action(TopDownAction::Action);
+        game.step(2);
+        let bullet_velocity = game
+            .app
+            .world
+            .query_filtered::<&Velocity, With<crate::bullet::Damager>>()
+            .single(&game.app.world)
+            .linvel;
+        assert!(bullet_velocity.normalize().abs_diff_eq(heading, 0.001));
+    }
+
+    #[test]
+    fn tutorial_triggers_fire_in_order() {
+        let mut game = TestGame::new();
+        game.app
+            .init_resource::<FiredTriggers>()
+            .add_systems(Update, record_triggers);
+
+        // Up the corridor, right along its top and down to the rocks which
+        // block the way to deep space.  Tiles are 50 pixels wide, flying
+        // between two rows keeps the ship off the walls.
+        let simply_forward = game.trigger_position("SimplyForward");
+        let turned_right = game.trigger_position("TurnedRight");
+        let deep_space = game.trigger_position("DeepSpace");
+        let waypoints = [
+            simply_forward,
+            simply_forward + Vec2::new(0.0, 25.0),
+            turned_right + Vec2::new(25.0, 25.0),
+            Vec2::new(turned_right.x + 25.0, deep_space.y - 25.0),
+        ];
+        for waypoint in waypoints {
+            let mut frames = 0;
+            while !steer_to(&mut game, waypoint) {
+                game.step(1);
+                frames += 1;
+                assert!(frames < 600, "stuck on the way to {}", waypoint);
+            }
+        }
+        let mut frames = 0;
+        while game.app.world.resource::<FiredTriggers>().0.len() < 3 {
+            steer_to(&mut game, deep_space - Vec2::new(0.0, 25.0));
+            game.send_action(TopDownAction::Action);
+            game.step(1);
+            frames += 1;
+            assert!(frames < 600, "the rocks are still in the way");
+        }
+        game.step(10);
+
+        let order = ["SimplyForward", "TurnedRight", "DeepSpace"];
+        assert_eq!(game.app.world.resource::<FiredTriggers>().0, order);
+        assert!(game.app.world.resource::<InGameState>().block_controls);
+        assert_eq!(
+            game.app
+                .world
+                .resource::<stars::StarMaterialSettings>()
+                .desired_speed_x,
+            10000.0
+        );
     }
-    Ok(())
 }
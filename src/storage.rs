@@ -0,0 +1,72 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[cfg(not(target_arch = "wasm32"))]
+    #[error("could not access file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[cfg(target_arch = "wasm32")]
+    #[error("could not access local storage")]
+    LocalStorage,
+}
+
+/// Where text like the keymap or the save game is kept between runs.
+pub enum Storage {
+    #[cfg(not(target_arch = "wasm32"))]
+    File(std::path::PathBuf),
+
+    #[cfg(target_arch = "wasm32")]
+    LocalStorage(&'static str),
+
+    /// Only kept while the game runs, like in tests.
+    Memory(Option<String>),
+}
+
+#[cfg(target_arch = "wasm32")]
+fn local_storage() -> Result<web_sys::Storage, StorageError> {
+    web_sys::window()
+        .and_then(|window| window.local_storage().ok().flatten())
+        .ok_or(StorageError::LocalStorage)
+}
+
+impl Storage {
+    /// A file on native platforms and the `localStorage` entry with the same
+    /// name in the browser.
+    pub fn platform_default(name: &'static str) -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        return Storage::File(name.into());
+        #[cfg(target_arch = "wasm32")]
+        return Storage::LocalStorage(name);
+    }
+
+    /// Reads the stored text, `None` if nothing was stored yet.
+    pub fn read(&self) -> Result<Option<String>, StorageError> {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Storage::File(path) => match std::fs::read_to_string(path) {
+                Ok(source) => Ok(Some(source)),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err.into()),
+            },
+            #[cfg(target_arch = "wasm32")]
+            Storage::LocalStorage(key) => local_storage()?
+                .get_item(key)
+                .map_err(|_| StorageError::LocalStorage),
+            Storage::Memory(source) => Ok(source.clone()),
+        }
+    }
+
+    pub fn write(&mut self, source: &str) -> Result<(), StorageError> {
+        match self {
+            #[cfg(not(target_arch = "wasm32"))]
+            Storage::File(path) => std::fs::write(path, source)?,
+            #[cfg(target_arch = "wasm32")]
+            Storage::LocalStorage(key) => local_storage()?
+                .set_item(key, source)
+                .map_err(|_| StorageError::LocalStorage)?,
+            Storage::Memory(stored) => *stored = Some(source.to_string()),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use bevy::ecs::system::CommandQueue;
+use bevy::input::gamepad::{
+    GamepadAxisChangedEvent, GamepadConnection, GamepadConnectionEvent, GamepadEvent, GamepadInfo,
+};
+use bevy::input::keyboard::{Key, KeyboardInput, NativeKey};
+use bevy::input::ButtonState;
+use bevy::prelude::*;
+use bevy::time::TimeUpdateStrategy;
+use bevy_rapier2d::prelude::*;
+use some_bevy_tools::{audio_loop, controller_2d::TopDownAction, input};
+use uuid::Uuid;
+
+use crate::{
+    assets::{ImageAssets, MapAssets, MusicAssets},
+    keymap::KeymapStorage,
+    map_asset::{read_map_asset, MapAsset},
+    map_builder::{MapDraft, TileType, TriggerAction},
+    save::SaveStorage,
+    ship::{Player, Ship},
+    stars,
+    storage::Storage,
+    GamePlugin, GameState,
+};
+
+use crate::replay::FRAME_TIME;
+
+/// Far away from the maps of the game, so nothing else gets in the way.
+pub const OPEN_SPACE: Vec2 = Vec2::new(-5000.0, -5000.0);
+
+/// Runs the game headless without rendering, audio or user input.
+///
+/// Image and music assets are dummy handles while the maps are read from the
+/// assets folder, so the game starts right in [`GameState::InGame`].  The
+/// keymap and the save game are only kept in memory.
+pub struct TestGame {
+    pub app: App,
+}
+
+impl TestGame {
+    pub fn new() -> Self {
+        Self::with_setup(|_| {})
+    }
+
+    /// Lets `setup` insert resources before the game plugin is added.
+    pub fn with_setup(setup: impl FnOnce(&mut App)) -> Self {
+        let mut app = App::new();
+        app.insert_resource(KeymapStorage(Storage::Memory(None)))
+            .insert_resource(SaveStorage(Storage::Memory(None)));
+        setup(&mut app);
+        app.insert_resource(RapierConfiguration {
+            gravity: Vec2::ZERO,
+            ..Default::default()
+        })
+        .insert_resource(TimeUpdateStrategy::ManualDuration(FRAME_TIME))
+        .add_plugins((
+            MinimalPlugins,
+            TransformPlugin,
+            HierarchyPlugin,
+            bevy::input::InputPlugin,
+            AssetPlugin::default(),
+        ))
+        .init_asset::<Image>()
+        .init_asset::<Mesh>()
+        .init_asset::<stars::StarMaterial>()
+        .init_asset::<audio_loop::LoopableAudioSource>()
+        .add_event::<audio_loop::AudioLoopEvent>()
+        .add_event::<input::ActionEvent<TopDownAction>>()
+        .add_plugins(RapierPhysicsPlugin::<NoUserData>::pixels_per_meter(1.0))
+        .add_plugins(GamePlugin)
+        .insert_resource(ImageAssets::default())
+        .insert_resource(MusicAssets::default());
+
+        let mut maps = app.world.resource_mut::<Assets<MapAsset>>();
+        let map_assets = MapAssets {
+            tutorial: maps.add(read_map_asset("tutorial")),
+            level_1: maps.add(read_map_asset("level_1")),
+        };
+        app.insert_resource(map_assets)
+            .insert_resource(NextState(Some(GameState::InGame)));
+        app.update();
+        Self { app }
+    }
+
+    /// Runs the given amount of frames.
+    pub fn step(&mut self, frames: usize) {
+        for _ in 0..frames {
+            self.app.update();
+        }
+    }
+
+    /// Sends an action as if the player had pressed the matching key.
+    pub fn send_action(&mut self, action: TopDownAction) {
+        self.app.world.send_event(input::ActionEvent { action });
+    }
+
+    /// Presses and releases a key, one frame each.
+    pub fn tap_key(&mut self, key_code: KeyCode) {
+        for state in [ButtonState::Pressed, ButtonState::Released] {
+            self.app.world.send_event(KeyboardInput {
+                key_code,
+                logical_key: Key::Unidentified(NativeKey::Unidentified),
+                state,
+                window: Entity::PLACEHOLDER,
+            });
+            self.app.update();
+        }
+    }
+
+    /// Plugs in a gamepad, it's there from the next frame on.
+    pub fn connect_gamepad(&mut self) -> Gamepad {
+        let gamepad = Gamepad::new(0);
+        self.app
+            .world
+            .send_event(GamepadEvent::Connection(GamepadConnectionEvent {
+                gamepad,
+                connection: GamepadConnection::Connected(GamepadInfo {
+                    name: "Test".to_string(),
+                }),
+            }));
+        self.app.update();
+        gamepad
+    }
+
+    /// Tilts the left stick, it stays there until it's moved again.
+    pub fn move_left_stick(&mut self, gamepad: Gamepad, stick: Vec2) {
+        for (axis_type, value) in [
+            (GamepadAxisType::LeftStickX, stick.x),
+            (GamepadAxisType::LeftStickY, stick.y),
+        ] {
+            self.app
+                .world
+                .send_event(GamepadEvent::Axis(GamepadAxisChangedEvent::new(
+                    gamepad, axis_type, value,
+                )));
+        }
+    }
+
+    pub fn state<S: States>(&self) -> S {
+        self.app.world.resource::<State<S>>().get().clone()
+    }
+
+    /// Runs something which needs `Commands` and applies them right away.
+    pub fn with_commands(&mut self, f: impl FnOnce(&mut Commands, &ImageAssets)) {
+        let mut queue = CommandQueue::default();
+        let image_assets = self.app.world.resource::<ImageAssets>().clone();
+        let mut commands = Commands::new(&mut queue, &self.app.world);
+        f(&mut commands, &image_assets);
+        queue.apply(&mut self.app.world);
+    }
+
+    /// Spawns a map centered on [`OPEN_SPACE`] with one line of `source` per
+    /// row and returns its id, its tiles are there once this returns.
+    pub fn spawn_map(
+        &mut self,
+        source: &str,
+        tiles: impl Fn(char) -> Option<TileType> + 'static,
+    ) -> Uuid {
+        let rows = source.lines().collect::<Vec<_>>();
+        let map = MapDraft::from_str_array(&rows, Box::new(tiles))
+            .unwrap()
+            .to_map((0, 0));
+        self.with_commands(|commands, image_assets| {
+            map.spawn_tiles(commands, image_assets, OPEN_SPACE);
+        });
+        self.step(1);
+        map.id
+    }
+
+    pub fn player(&mut self) -> Entity {
+        self.app
+            .world
+            .query_filtered::<Entity, (With<Ship>, With<Player>)>()
+            .single(&self.app.world)
+    }
+
+    /// Moves the player to the given position and stops it.
+    pub fn teleport_player(&mut self, position: Vec2) {
+        let player = self.player();
+        let mut entity = self.app.world.entity_mut(player);
+        entity.get_mut::<Transform>().unwrap().translation = position.extend(0.0);
+        *entity.get_mut::<Velocity>().unwrap() = Velocity::zero();
+    }
+
+    /// Position of the first trigger with the given action.
+    pub fn trigger_position(&mut self, action: &str) -> Vec2 {
+        self.app
+            .world
+            .query::<(&TriggerAction, &Transform)>()
+            .iter(&self.app.world)
+            .find(|(trigger, _)| trigger.0 == action)
+            .map(|(_, transform)| transform.translation.xy())
+            .unwrap()
+    }
+}
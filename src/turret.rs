@@ -0,0 +1,192 @@
+#![allow(clippy::type_complexity)]
+
+use bevy::prelude::*;
+use bevy_rapier2d::prelude::*;
+use some_bevy_tools::{health::Health, physics2d};
+
+use crate::{
+    assets::ImageAssets,
+    bullet,
+    faction::Faction,
+    menu::PauseState,
+    respawn::Respawning,
+    ship::Player,
+    weapon::{Weapon, WeaponKind},
+    GameState, StaticWall,
+};
+
+/// A fixed gun which shoots at the player whenever it can see it.
+#[derive(Component)]
+pub struct Turret {
+    /// The player is ignored beyond this distance.
+    pub range: f32,
+}
+
+impl Default for Turret {
+    fn default() -> Self {
+        Self { range: 500.0 }
+    }
+}
+
+pub fn spawn_turret(
+    commands: &mut Commands,
+    image_assets: &ImageAssets,
+    position: Vec2,
+    health: f32,
+) -> Entity {
+    commands
+        .spawn((
+            SpriteBundle {
+                texture: image_assets.ship.clone(),
+                transform: Transform::from_translation(position.extend(0.0)),
+                sprite: Sprite {
+                    color: Color::rgb(0.7, 0.4, 1.0),
+                    custom_size: Some(Vec2::new(50.0, 50.0)),
+                    ..default()
+                },
+                ..default()
+            },
+            physics2d::PhysicsBundle::fixed_rectangle(50.0, 50.0),
+            Health::new(0.0, health),
+            Faction::Enemy,
+            Weapon {
+                fire_rate: 0.8,
+                projectile_speed: 250.0,
+                ..WeaponKind::Blaster.weapon()
+            },
+            Turret::default(),
+        ))
+        .id()
+}
+
+/// Turns turrets towards the player and fires once the player is in range
+/// and no wall is in between.
+pub fn turret_system(
+    mut commands: Commands,
+    image_assets: Res<ImageAssets>,
+    rapier_context: Res<RapierContext>,
+    mut turret_query: Query<(Entity, &Turret, &mut Transform, &mut Weapon), Without<Player>>,
+    player_query: Query<&Transform, (With<Player>, Without<Respawning>)>,
+    wall_query: Query<(), With<StaticWall>>,
+) {
+    let Ok(player_transform) = player_query.get_single() else {
+        return;
+    };
+    let player_position = player_transform.translation.xy();
+    for (turret_entity, turret, mut transform, mut weapon) in turret_query.iter_mut() {
+        let position = transform.translation.xy();
+        let distance = position.distance(player_position);
+        if distance > turret.range || distance < f32::EPSILON {
+            continue;
+        }
+        let direction = (player_position - position) / distance;
+        transform.rotation = Quat::from_rotation_z(Vec2::Y.angle_between(direction));
+
+        let is_wall = |entity| wall_query.contains(entity);
+        let blocked = rapier_context
+            .cast_ray(
+                position,
+                direction,
+                distance,
+                true,
+                QueryFilter::default().predicate(&is_wall),
+            )
+            .is_some();
+        if blocked {
+            continue;
+        }
+        bullet::fire_weapon(
+            &mut commands,
+            &image_assets,
+            position,
+            direction,
+            Vec2::ZERO,
+            &mut weapon,
+            turret_entity,
+            Some(Faction::Enemy),
+        );
+    }
+}
+
+pub struct TurretPlugin;
+impl Plugin for TurretPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            turret_system
+                .run_if(in_state(GameState::InGame).and_then(in_state(PauseState::Running))),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bullet::Damager;
+    use crate::map_builder::TileType;
+    use crate::test_support::{TestGame, OPEN_SPACE};
+
+    /// Spawns a row of tiles with a turret as `Y` and returns the turret.
+    fn spawn_row(game: &mut TestGame, row: &'static str) -> Entity {
+        game.spawn_map(row, |c| match c {
+            'Y' => Some(TileType::Turret(40.0)),
+            'X' => Some(TileType::Wall),
+            _ => None,
+        });
+        game.app
+            .world
+            .query_filtered::<Entity, With<Turret>>()
+            .single(&game.app.world)
+    }
+
+    fn turret_bullets(game: &mut TestGame, turret: Entity) -> usize {
+        game.app
+            .world
+            .query::<&Damager>()
+            .iter(&game.app.world)
+            .filter(|damager| damager.owner == Some(turret))
+            .count()
+    }
+
+    #[test]
+    fn shoots_player_in_sight() {
+        let mut game = TestGame::new();
+        let turret = spawn_row(&mut game, "Y..");
+        game.teleport_player(OPEN_SPACE + Vec2::new(150.0, 0.0));
+        game.step(2);
+        assert_eq!(turret_bullets(&mut game, turret), 1);
+        let rotation = game.app.world.get::<Transform>(turret).unwrap().rotation;
+        assert!((rotation * Vec3::Y).abs_diff_eq(Vec3::X, 0.001));
+    }
+
+    #[test]
+    fn walls_block_line_of_sight() {
+        let mut game = TestGame::new();
+        let turret = spawn_row(&mut game, "YX.");
+        game.teleport_player(OPEN_SPACE + Vec2::new(150.0, 0.0));
+        game.step(60);
+        assert_eq!(turret_bullets(&mut game, turret), 0);
+    }
+
+    #[test]
+    fn ignores_player_out_of_range() {
+        let mut game = TestGame::new();
+        let turret = spawn_row(&mut game, "Y");
+        game.teleport_player(OPEN_SPACE + Vec2::new(0.0, 1000.0));
+        game.step(60);
+        assert_eq!(turret_bullets(&mut game, turret), 0);
+    }
+
+    #[test]
+    fn can_be_destroyed() {
+        let mut game = TestGame::new();
+        let turret = spawn_row(&mut game, "Y");
+        game.app
+            .world
+            .get_mut::<Health>(turret)
+            .unwrap()
+            .modify(-100.0);
+        game.step(2);
+        assert!(game.app.world.get_entity(turret).is_none());
+    }
+}
@@ -0,0 +1,315 @@
+use std::fmt;
+use std::str::FromStr;
+
+use bevy::prelude::*;
+use some_bevy_tools::{
+    collision_detection::{self, CollisionEventStart},
+    despawn::AutoDespawn,
+};
+
+use crate::{map_builder::MapDraftError, menu::PauseState, ship::Ship, GameState};
+
+/// The weapons which exist in the game.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum WeaponKind {
+    /// Slow single shots, every ship starts with it.
+    #[default]
+    Blaster,
+
+    /// Fires a fan of weak projectiles.
+    Scatter,
+
+    /// Fast stream of weak projectiles.
+    Rapid,
+}
+
+impl WeaponKind {
+    pub fn weapon(&self) -> Weapon {
+        match self {
+            WeaponKind::Blaster => Weapon {
+                kind: *self,
+                fire_rate: 2.0,
+                projectile_speed: 100.0,
+                damage: 10.0,
+                spread: 0.0,
+                burst: 1,
+                projectile_look: Sprite {
+                    custom_size: Some(Vec2::new(10.0, 10.0)),
+                    ..default()
+                },
+                reload: 0.0,
+                level: 0,
+            },
+            WeaponKind::Scatter => Weapon {
+                kind: *self,
+                fire_rate: 1.25,
+                projectile_speed: 250.0,
+                damage: 6.0,
+                spread: 0.6,
+                burst: 3,
+                projectile_look: Sprite {
+                    color: Color::rgb(1.0, 0.8, 0.3),
+                    custom_size: Some(Vec2::new(8.0, 8.0)),
+                    ..default()
+                },
+                reload: 0.0,
+                level: 0,
+            },
+            WeaponKind::Rapid => Weapon {
+                kind: *self,
+                fire_rate: 8.0,
+                projectile_speed: 400.0,
+                damage: 3.0,
+                spread: 0.0,
+                burst: 1,
+                projectile_look: Sprite {
+                    color: Color::rgb(0.4, 0.9, 1.0),
+                    custom_size: Some(Vec2::new(6.0, 6.0)),
+                    ..default()
+                },
+                reload: 0.0,
+                level: 0,
+            },
+        }
+    }
+}
+
+impl FromStr for WeaponKind {
+    type Err = MapDraftError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "blaster" => Ok(WeaponKind::Blaster),
+            "scatter" => Ok(WeaponKind::Scatter),
+            "rapid" => Ok(WeaponKind::Rapid),
+            _ => Err(MapDraftError::UnknownWeapon(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for WeaponKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WeaponKind::Blaster => write!(f, "blaster"),
+            WeaponKind::Scatter => write!(f, "scatter"),
+            WeaponKind::Rapid => write!(f, "rapid"),
+        }
+    }
+}
+
+/// Upgrades a weapon can get, each one adds a quarter of the damage it
+/// started with.
+pub const MAX_WEAPON_LEVEL: u32 = 3;
+
+/// Describes how a ship shoots.
+#[derive(Component, Clone, Debug)]
+pub struct Weapon {
+    pub kind: WeaponKind,
+
+    /// Shots per second.
+    pub fire_rate: f32,
+    pub projectile_speed: f32,
+    pub damage: f32,
+
+    /// Angle in radians the projectiles of one shot are fanned out over.
+    pub spread: f32,
+
+    /// Projectiles per shot.
+    pub burst: u32,
+
+    /// Tint and size of the projectiles, which all use the bullet image.
+    pub projectile_look: Sprite,
+
+    /// Seconds until the weapon can fire again.
+    pub reload: f32,
+
+    /// Upgrades picked up since the weapon was picked up.
+    pub level: u32,
+}
+
+impl Default for Weapon {
+    fn default() -> Self {
+        WeaponKind::default().weapon()
+    }
+}
+
+impl Weapon {
+    pub fn is_ready(&self) -> bool {
+        self.reload <= 0.0
+    }
+
+    /// Starts reloading after a shot.
+    pub fn fire(&mut self) {
+        self.reload = 1.0 / self.fire_rate;
+    }
+
+    /// Makes the projectiles stronger, up to [`MAX_WEAPON_LEVEL`].
+    pub fn upgrade(&mut self) {
+        if self.level >= MAX_WEAPON_LEVEL {
+            return;
+        }
+        let base_damage = self.damage / (1.0 + self.level as f32 * 0.25);
+        self.level += 1;
+        self.damage = base_damage * (1.0 + self.level as f32 * 0.25);
+    }
+
+    /// Velocities of the projectiles of one shot relative to the ship, spread
+    /// evenly around `direction`.
+    pub fn projectile_velocities(&self, direction: Vec2) -> Vec<Vec2> {
+        let burst = self.burst.max(1);
+        (0..burst)
+            .map(|index| {
+                let angle = if burst == 1 {
+                    0.0
+                } else {
+                    -self.spread / 2.0 + self.spread * index as f32 / (burst - 1) as f32
+                };
+                Vec2::from_angle(angle).rotate(direction) * self.projectile_speed
+            })
+            .collect()
+    }
+}
+
+/// A weapon lying around which replaces the weapon of the ship that flies
+/// through it.
+#[derive(Component, Clone, Copy, Default)]
+pub struct WeaponPickup(pub WeaponKind);
+
+pub fn reload_weapons(time: Res<Time>, mut query: Query<&mut Weapon>) {
+    for mut weapon in query.iter_mut() {
+        if !weapon.is_ready() {
+            weapon.reload -= time.delta_seconds();
+        }
+    }
+}
+
+pub fn pick_up_weapon(
+    mut commands: Commands,
+    mut pickup_events: EventReader<CollisionEventStart<Ship, WeaponPickup>>,
+    pickup_query: Query<&WeaponPickup>,
+    mut weapon_query: Query<&mut Weapon>,
+) {
+    for CollisionEventStart(ship, pickup_entity, _) in pickup_events.read() {
+        let Ok(WeaponPickup(kind)) = pickup_query.get(*pickup_entity) else {
+            continue;
+        };
+        if let Ok(mut weapon) = weapon_query.get_mut(*ship) {
+            bevy::log::info!("Picked up {}", kind);
+            *weapon = kind.weapon();
+            commands
+                .entity(*pickup_entity)
+                .remove::<WeaponPickup>()
+                .insert(AutoDespawn::with_frames(0));
+        }
+    }
+}
+
+pub struct WeaponPlugin;
+impl Plugin for WeaponPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugins(collision_detection::CollisionDetectionPlugin::<
+            Ship,
+            WeaponPickup,
+        >::default())
+            .add_systems(
+                Update,
+                (reload_weapons, pick_up_weapon)
+                    .run_if(in_state(GameState::InGame).and_then(in_state(PauseState::Running))),
+            );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bullet::Damager;
+    use crate::map_builder::TileType;
+    use crate::test_support::{TestGame, OPEN_SPACE};
+    use some_bevy_tools::controller_2d::TopDownAction;
+
+    fn bullet_count(game: &mut TestGame) -> usize {
+        game.app
+            .world
+            .query_filtered::<(), With<Damager>>()
+            .iter(&game.app.world)
+            .count()
+    }
+
+    fn hold_fire(game: &mut TestGame, frames: usize) {
+        for _ in 0..frames {
+            game.send_action(TopDownAction::Action);
+            game.step(1);
+        }
+    }
+
+    #[test]
+    fn burst_is_spread_around_the_direction() {
+        let weapon = WeaponKind::Scatter.weapon();
+        let velocities = weapon.projectile_velocities(Vec2::Y);
+        assert_eq!(velocities.len(), 3);
+        assert!(velocities[1].abs_diff_eq(Vec2::Y * weapon.projectile_speed, 0.001));
+        assert!((velocities[0].angle_between(velocities[2]).abs() - weapon.spread).abs() < 0.001);
+        for velocity in velocities {
+            assert!((velocity.length() - weapon.projectile_speed).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn fire_rate_limits_shots() {
+        let mut game = TestGame::new();
+        hold_fire(&mut game, 20);
+        assert_eq!(bullet_count(&mut game), 1);
+
+        let player = game.player();
+        game.app
+            .world
+            .entity_mut(player)
+            .insert(WeaponKind::Rapid.weapon());
+        hold_fire(&mut game, 20);
+        assert!(bullet_count(&mut game) >= 3);
+    }
+
+    #[test]
+    fn scatter_fires_a_burst() {
+        let mut game = TestGame::new();
+        let player = game.player();
+        game.app
+            .world
+            .entity_mut(player)
+            .insert(WeaponKind::Scatter.weapon());
+        hold_fire(&mut game, 1);
+        game.step(1);
+        assert_eq!(bullet_count(&mut game), 3);
+    }
+
+    #[test]
+    fn flying_through_pickup_swaps_weapon() {
+        let mut game = TestGame::new();
+        game.spawn_map("W", |c| {
+            (c == 'W').then_some(TileType::Weapon(WeaponKind::Rapid))
+        });
+        game.teleport_player(OPEN_SPACE);
+        game.step(5);
+
+        let player = game.player();
+        assert_eq!(
+            game.app.world.get::<Weapon>(player).unwrap().kind,
+            WeaponKind::Rapid
+        );
+        let pickups = game
+            .app
+            .world
+            .query::<&WeaponPickup>()
+            .iter(&game.app.world)
+            .count();
+        assert_eq!(pickups, 0);
+    }
+
+    #[test]
+    fn weapon_tile_definition() {
+        let tile_type = "weapon scatter".parse::<TileType>().unwrap();
+        assert!(matches!(tile_type, TileType::Weapon(WeaponKind::Scatter)));
+        assert_eq!(tile_type.to_string(), "weapon scatter");
+        assert!("weapon laser".parse::<TileType>().is_err());
+    }
+}